@@ -7,7 +7,8 @@ interface TransactionInput {
     id: number;
     transaction_id: number;
     previous_output: string;
-    value: number;
+    // null while the output it spends is not known
+    value: number | null;
 }
 
 interface TransactionOutput {
@@ -22,8 +23,12 @@ interface Transaction {
     block_height: number;
     hash: string;
     btc: number;
-    fee: number;
+    // null while the outputs it spends are not known
+    fee: number | null;
     time: number;
+    // at the block's price; null while that price is not known
+    fiat_value: number | null;
+    fiat_fee: number | null;
 }
 
 interface BlockDetailData {
@@ -37,6 +42,9 @@ interface BlockDetailData {
         size: number;
         weight: number;
     };
+    currency: string;
+    // BTC price at the block's timestamp; null while it is not known
+    btc_price: number | null;
     transactions: Transaction[];
     inputs: TransactionInput[];
     outputs: TransactionOutput[];
@@ -47,8 +55,7 @@ const BlockDetail: React.FC = () => {
     const [blockDetail, setBlockDetail] = useState<BlockDetailData | null>(null);
     const [loading, setLoading] = useState(true);
     const [currentPage, setCurrentPage] = useState(1);
-    const [btcToUsdRate, setBtcToUsdRate] = useState<number | null>(null);
-    const [isUsd, setIsUsd] = useState(false);
+    const [isFiat, setIsFiat] = useState(false);
     const transactionsPerPage = 10;
 
     useEffect(() => {
@@ -67,25 +74,11 @@ const BlockDetail: React.FC = () => {
             }
         };
 
-        const fetchBtcToUsdRate = async () => {
-            try {
-                const response = await fetch('https://api.coingecko.com/api/v3/simple/price?ids=bitcoin&vs_currencies=usd');
-                if (!response.ok) {
-                    throw new Error("Failed to fetch BTC to USD rate");
-                }
-                const data = await response.json();
-                setBtcToUsdRate(data.bitcoin.usd);
-            } catch (error) {
-                console.error("Error fetching BTC to USD rate:", error);
-            }
-        };
-
         fetchBlockDetail();
-        fetchBtcToUsdRate();
     }, [height]);
 
     const toggleCurrency = () => {
-        setIsUsd(!isUsd);
+        setIsFiat(!isFiat);
     };
 
     const paginate = (direction: string) => {
@@ -107,12 +100,26 @@ const BlockDetail: React.FC = () => {
     const indexOfLastTransaction = currentPage * transactionsPerPage;
     const indexOfFirstTransaction = indexOfLastTransaction - transactionsPerPage;
     const currentTransactions = blockDetail.transactions.slice(indexOfFirstTransaction, indexOfLastTransaction);
+    const { currency, btc_price: btcPrice } = blockDetail;
+
+    // Fiat amounts are at the block's price, as the API reports them, not today's.
+    const formatFiat = (fiat: number | null) =>
+        fiat === null ? 'unknown' : `${fiat.toFixed(2)} ${currency}`;
+    const formatSats = (sats: number | null) => {
+        if (sats === null) {
+            return 'unknown';
+        }
+        if (isFiat) {
+            return formatFiat(btcPrice === null ? null : sats / 100000000 * btcPrice);
+        }
+        return `${(sats / 100000000).toFixed(8)} BTC`;
+    };
 
     return (
         <Container>
             <div className="currency-toggle">
-                <Button onClick={toggleCurrency} className="currency-button">
-                    {isUsd ? 'Switch to BTC' : 'Switch to USD'}
+                <Button onClick={toggleCurrency} className="currency-button" disabled={btcPrice === null && !isFiat}>
+                    {isFiat ? 'Switch to BTC' : `Switch to ${currency}`}
                 </Button>
             </div>
             <h2 className="my-4">Block Details - Height {blockDetail.block_info.height}</h2>
@@ -124,10 +131,10 @@ const BlockDetail: React.FC = () => {
                             <Card.Header>Transaction {tx.hash}</Card.Header>
                             <Card.Body>
                                 <Card.Text>
-                                    BTC: {isUsd ? `$ ${(tx.btc / 100000000 * (btcToUsdRate || 0)).toFixed(2)} USD` : `${(tx.btc / 100000000).toFixed(8)} BTC`}
+                                    BTC: {isFiat ? formatFiat(tx.fiat_value) : `${tx.btc.toFixed(8)} BTC`}
                                 </Card.Text>
                                 <Card.Text>
-                                    Fee: {isUsd ? `$ ${(tx.fee / 100000000 * (btcToUsdRate || 0)).toFixed(2)} USD` : `${(tx.fee / 100000000).toFixed(8)} BTC`}
+                                    Fee: {isFiat ? formatFiat(tx.fiat_fee) : formatSats(tx.fee)}
                                 </Card.Text>
                                 <Card.Text>Time: {new Date(tx.time * 1000).toLocaleString()}</Card.Text>
                                 <Row className="transaction-io">
@@ -138,7 +145,7 @@ const BlockDetail: React.FC = () => {
                                             .map((input) => (
                                                 <p key={input.id} className="input-output">
                                                     {input.previous_output}
-                                                    {isUsd ? `$ ${(input.value / 100000000 * (btcToUsdRate || 0)).toFixed(2)} USD` : `${(input.value / 100000000).toFixed(8)} BTC`}
+                                                    {formatSats(input.value)}
                                                 </p>
                                             ))}
                                     </Col>
@@ -149,7 +156,7 @@ const BlockDetail: React.FC = () => {
                                             .map((output) => (
                                                 <p key={output.id} className="input-output">
                                                     {output.address || " "}
-                                                    {isUsd ? `$ ${(output.value / 100000000 * (btcToUsdRate || 0)).toFixed(2)} USD` : `${(output.value / 100000000).toFixed(8)} BTC`}
+                                                    {formatSats(output.value)}
                                                 </p>
                                             ))}
                                     </Col>
@@ -18,15 +18,12 @@ const BlockHeightComponent: React.FC = () => {
     const [currentPage, setCurrentPage] = useState(1);
     const blocksPerPage = 10;
 
+    // The API pages through the blocks highest first, so only the shown page is fetched.
     useEffect(() => {
-        fetch('http://localhost:8000/block-info')
+        fetch(`http://localhost:8000/block-info?page=${currentPage}&per_page=${blocksPerPage}`)
             .then(response => response.json())
             .then(data => setBlockData(data));
-    }, []);
-
-    const indexOfLastBlock = currentPage * blocksPerPage;
-    const indexOfFirstBlock = indexOfLastBlock - blocksPerPage;
-    const currentBlocks = blockData.slice(indexOfFirstBlock, indexOfLastBlock);
+    }, [currentPage]);
 
     const paginate = (pageNumber: number) => setCurrentPage(pageNumber);
 
@@ -46,7 +43,7 @@ const BlockHeightComponent: React.FC = () => {
                 </tr>
                 </thead>
                 <tbody>
-                {currentBlocks.map(block => (
+                {blockData.map(block => (
                     <tr key={block.height}>
                         <td>
                             <Link to={`/block/${block.height}`}>{block.height}</Link>
@@ -64,7 +61,7 @@ const BlockHeightComponent: React.FC = () => {
             <Pagination className="justify-content-center">
                 <Pagination.Prev onClick={() => paginate(currentPage - 1)} disabled={currentPage === 1} />
                 <Pagination.Item>{currentPage}</Pagination.Item>
-                <Pagination.Next onClick={() => paginate(currentPage + 1)} disabled={indexOfLastBlock >= blockData.length} />
+                <Pagination.Next onClick={() => paginate(currentPage + 1)} disabled={blockData.length < blocksPerPage} />
             </Pagination>
         </Container>
     );
@@ -0,0 +1,6 @@
+CREATE TABLE backfill_progress (
+                                   start_height INT PRIMARY KEY,
+                                   end_height INT NOT NULL,
+                                   next_height INT NOT NULL,
+                                   updated_at TIMESTAMP NOT NULL
+);
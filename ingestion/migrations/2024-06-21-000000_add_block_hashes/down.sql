@@ -0,0 +1,3 @@
+ALTER TABLE block_info DROP CONSTRAINT IF EXISTS unique_hash;
+ALTER TABLE block_info DROP COLUMN IF EXISTS previous_hash;
+ALTER TABLE block_info DROP COLUMN IF EXISTS hash;
@@ -0,0 +1,3 @@
+ALTER TABLE block_info ADD COLUMN hash VARCHAR;
+ALTER TABLE block_info ADD COLUMN previous_hash VARCHAR;
+ALTER TABLE block_info ADD CONSTRAINT unique_hash UNIQUE (hash);
@@ -0,0 +1,2 @@
+-- The partial transactions cleared by up.sql are not restored; they are re-ingested in full.
+ALTER TABLE block_info DROP COLUMN IF EXISTS complete;
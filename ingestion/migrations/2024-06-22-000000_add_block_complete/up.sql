@@ -0,0 +1,20 @@
+ALTER TABLE block_info ADD COLUMN complete BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Blocks stored so far kept at most their first 25 transactions, without input outpoints, and are
+-- re-ingested in full now that they are incomplete. Rewriting skips transactions that already
+-- exist, so their partial rows are cleared here rather than left in place for good.
+DELETE FROM transaction_inputs
+WHERE transaction_id IN (
+    SELECT t.id FROM transactions t
+    JOIN block_info b ON b.height = t.block_height
+    WHERE NOT b.complete
+);
+DELETE FROM transaction_outputs
+WHERE transaction_id IN (
+    SELECT t.id FROM transactions t
+    JOIN block_info b ON b.height = t.block_height
+    WHERE NOT b.complete
+);
+DELETE FROM transactions t
+USING block_info b
+WHERE b.height = t.block_height AND NOT b.complete;
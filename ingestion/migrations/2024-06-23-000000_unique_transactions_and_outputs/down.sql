@@ -0,0 +1,3 @@
+ALTER TABLE transaction_outputs DROP CONSTRAINT IF EXISTS unique_outpoint;
+ALTER TABLE transaction_outputs DROP COLUMN IF EXISTS vout;
+ALTER TABLE transactions DROP CONSTRAINT IF EXISTS unique_tx_hash_height;
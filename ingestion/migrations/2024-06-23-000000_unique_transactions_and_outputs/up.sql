@@ -0,0 +1,37 @@
+-- Earlier versions re-inserted the tip block's transactions on every poll; keep the first copy.
+DELETE FROM transaction_inputs
+WHERE transaction_id IN (
+    SELECT id FROM transactions t
+    WHERE EXISTS (
+        SELECT 1 FROM transactions o
+        WHERE o.hash = t.hash AND o.block_height = t.block_height AND o.id < t.id
+    )
+);
+DELETE FROM transaction_outputs
+WHERE transaction_id IN (
+    SELECT id FROM transactions t
+    WHERE EXISTS (
+        SELECT 1 FROM transactions o
+        WHERE o.hash = t.hash AND o.block_height = t.block_height AND o.id < t.id
+    )
+);
+DELETE FROM transactions t
+WHERE EXISTS (
+    SELECT 1 FROM transactions o
+    WHERE o.hash = t.hash AND o.block_height = t.block_height AND o.id < t.id
+);
+
+-- Before BIP30 two coinbase transactions were repeated in later blocks (d5d27987... at 91812 and
+-- 91842, e3bf3d07... at 91722 and 91880), so a txid is only unique within a block.
+ALTER TABLE transactions ADD CONSTRAINT unique_tx_hash_height UNIQUE (hash, block_height);
+
+ALTER TABLE transaction_outputs ADD COLUMN vout INT;
+UPDATE transaction_outputs o
+SET vout = numbered.vout
+FROM (
+    SELECT id, (ROW_NUMBER() OVER (PARTITION BY transaction_id ORDER BY id) - 1)::INT AS vout
+    FROM transaction_outputs
+) numbered
+WHERE o.id = numbered.id;
+ALTER TABLE transaction_outputs ALTER COLUMN vout SET NOT NULL;
+ALTER TABLE transaction_outputs ADD CONSTRAINT unique_outpoint UNIQUE (transaction_id, vout);
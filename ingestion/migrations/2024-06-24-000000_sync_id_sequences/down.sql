@@ -0,0 +1 @@
+-- Nothing to undo: sequence positions are left as they are.
@@ -0,0 +1,5 @@
+-- Rows used to be inserted with ids computed as max(id) + 1, leaving the SERIAL sequences behind.
+SELECT setval('block_info_id_seq', COALESCE((SELECT MAX(id) FROM block_info), 0) + 1, false);
+SELECT setval('transactions_id_seq', COALESCE((SELECT MAX(id) FROM transactions), 0) + 1, false);
+SELECT setval('transaction_inputs_id_seq', COALESCE((SELECT MAX(id) FROM transaction_inputs), 0) + 1, false);
+SELECT setval('transaction_outputs_id_seq', COALESCE((SELECT MAX(id) FROM transaction_outputs), 0) + 1, false);
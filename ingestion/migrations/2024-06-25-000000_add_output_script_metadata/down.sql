@@ -0,0 +1,5 @@
+UPDATE transactions SET btc = btc * 100000000.0;
+
+ALTER TABLE transaction_outputs DROP COLUMN IF EXISTS script_type;
+ALTER TABLE transaction_outputs DROP COLUMN IF EXISTS script_asm;
+ALTER TABLE transaction_outputs DROP COLUMN IF EXISTS script_hex;
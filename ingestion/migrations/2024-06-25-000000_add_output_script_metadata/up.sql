@@ -0,0 +1,6 @@
+ALTER TABLE transaction_outputs ADD COLUMN script_hex VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE transaction_outputs ADD COLUMN script_asm VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE transaction_outputs ADD COLUMN script_type VARCHAR NOT NULL DEFAULT '';
+
+-- Output values were summed in satoshis but stored as if they were BTC.
+UPDATE transactions SET btc = btc / 100000000.0;
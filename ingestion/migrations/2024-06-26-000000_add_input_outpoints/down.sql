@@ -0,0 +1,8 @@
+DROP INDEX IF EXISTS transaction_inputs_outpoint_idx;
+ALTER TABLE transaction_inputs DROP CONSTRAINT IF EXISTS unique_input;
+ALTER TABLE transaction_inputs DROP COLUMN IF EXISTS is_coinbase;
+ALTER TABLE transaction_inputs DROP COLUMN IF EXISTS witness;
+ALTER TABLE transaction_inputs DROP COLUMN IF EXISTS scriptsig;
+ALTER TABLE transaction_inputs DROP COLUMN IF EXISTS sequence;
+ALTER TABLE transaction_inputs DROP COLUMN IF EXISTS previous_vout;
+ALTER TABLE transaction_inputs DROP COLUMN IF EXISTS vin;
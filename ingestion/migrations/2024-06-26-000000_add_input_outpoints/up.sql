@@ -0,0 +1,25 @@
+ALTER TABLE transaction_inputs ADD COLUMN vin INT;
+UPDATE transaction_inputs i
+SET vin = numbered.vin
+FROM (
+    SELECT id, (ROW_NUMBER() OVER (PARTITION BY transaction_id ORDER BY id) - 1)::INT AS vin
+    FROM transaction_inputs
+) numbered
+WHERE i.id = numbered.id;
+ALTER TABLE transaction_inputs ALTER COLUMN vin SET NOT NULL;
+
+-- Rows stored before this migration only kept the previous txid; their vout is unknown.
+ALTER TABLE transaction_inputs ADD COLUMN previous_vout INT;
+ALTER TABLE transaction_inputs ADD COLUMN sequence BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE transaction_inputs ADD COLUMN scriptsig VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE transaction_inputs ADD COLUMN witness TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE transaction_inputs ADD COLUMN is_coinbase BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE transaction_inputs
+SET is_coinbase = TRUE
+WHERE previous_output = '0000000000000000000000000000000000000000000000000000000000000000';
+ALTER TABLE transaction_inputs ALTER COLUMN sequence DROP DEFAULT;
+ALTER TABLE transaction_inputs ALTER COLUMN scriptsig DROP DEFAULT;
+ALTER TABLE transaction_inputs ALTER COLUMN is_coinbase DROP DEFAULT;
+
+ALTER TABLE transaction_inputs ADD CONSTRAINT unique_input UNIQUE (transaction_id, vin);
+CREATE INDEX transaction_inputs_outpoint_idx ON transaction_inputs (previous_output, previous_vout);
@@ -0,0 +1,23 @@
+CREATE TABLE utxos (
+                       txid VARCHAR NOT NULL,
+                       vout INT NOT NULL,
+                       value BIGINT NOT NULL,
+                       script_type VARCHAR NOT NULL,
+                       address VARCHAR NOT NULL,
+                       height INT NOT NULL,
+                       PRIMARY KEY (txid, vout)
+);
+
+CREATE INDEX utxos_height_idx ON utxos (height);
+
+-- Seed the set from whatever has been ingested so far.
+INSERT INTO utxos (txid, vout, value, script_type, address, height)
+SELECT t.hash, o.vout, o.value, o.script_type, o.address, t.block_height
+FROM transaction_outputs o
+JOIN transactions t ON t.id = o.transaction_id
+WHERE o.script_type <> 'op_return'
+  AND NOT EXISTS (
+      SELECT 1 FROM transaction_inputs i
+      WHERE i.previous_output = t.hash AND i.previous_vout = o.vout
+  )
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS transaction_outputs_address_idx;
+DROP TABLE IF EXISTS addresses;
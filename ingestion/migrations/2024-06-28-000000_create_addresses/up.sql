@@ -0,0 +1,35 @@
+CREATE TABLE addresses (
+                           address VARCHAR PRIMARY KEY,
+                           funded_txo_count BIGINT NOT NULL,
+                           funded_txo_sum BIGINT NOT NULL,
+                           spent_txo_count BIGINT NOT NULL,
+                           spent_txo_sum BIGINT NOT NULL,
+                           tx_count BIGINT NOT NULL,
+                           first_seen_height INT NOT NULL,
+                           last_seen_height INT NOT NULL
+);
+
+CREATE INDEX transaction_outputs_address_idx ON transaction_outputs (address);
+
+-- Seed the index from whatever has been ingested so far.
+INSERT INTO addresses (address, funded_txo_count, funded_txo_sum, spent_txo_count, spent_txo_sum,
+                       tx_count, first_seen_height, last_seen_height)
+SELECT address,
+       SUM(funded_count)::BIGINT, SUM(funded_sum)::BIGINT, SUM(spent_count)::BIGINT, SUM(spent_sum)::BIGINT,
+       COUNT(DISTINCT tx_id)::BIGINT, MIN(height), MAX(height)
+FROM (
+    SELECT o.address, o.transaction_id AS tx_id, t.block_height AS height,
+           1 AS funded_count, o.value AS funded_sum, 0 AS spent_count, 0::BIGINT AS spent_sum
+    FROM transaction_outputs o
+    JOIN transactions t ON t.id = o.transaction_id
+    WHERE o.address <> ''
+    UNION ALL
+    SELECT po.address, i.transaction_id, ti.block_height, 0, 0::BIGINT, 1, po.value
+    FROM transaction_inputs i
+    JOIN transactions ti ON ti.id = i.transaction_id
+    JOIN transactions tpo ON tpo.hash = i.previous_output
+    JOIN transaction_outputs po ON po.transaction_id = tpo.id AND po.vout = i.previous_vout
+    WHERE po.address <> ''
+      AND NOT EXISTS (SELECT 1 FROM transactions e WHERE e.hash = tpo.hash AND e.block_height < tpo.block_height)
+) activity
+GROUP BY address;
@@ -0,0 +1,10 @@
+CREATE TABLE mempool_transactions (
+                                      txid VARCHAR PRIMARY KEY,
+                                      fee BIGINT NOT NULL,
+                                      vsize BIGINT NOT NULL,
+                                      weight BIGINT NOT NULL,
+                                      first_seen TIMESTAMP NOT NULL,
+                                      confirmed_height INT
+);
+
+CREATE INDEX mempool_transactions_confirmed_height_idx ON mempool_transactions (confirmed_height);
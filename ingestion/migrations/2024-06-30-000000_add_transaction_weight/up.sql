@@ -0,0 +1,3 @@
+-- Unknown for transactions stored before this migration; those stay at 0.
+ALTER TABLE transactions ADD COLUMN vsize INT NOT NULL DEFAULT 0;
+ALTER TABLE transactions ADD COLUMN weight INT NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS price_quotes_block_height_idx;
+DROP TABLE IF EXISTS price_quotes;
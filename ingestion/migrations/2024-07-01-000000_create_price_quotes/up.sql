@@ -0,0 +1,14 @@
+CREATE TABLE price_quotes (
+                              id SERIAL PRIMARY KEY,
+                              block_height INT NOT NULL,
+                              provider VARCHAR NOT NULL,
+                              price DOUBLE PRECISION NOT NULL,
+                              volume DOUBLE PRECISION,
+                              high DOUBLE PRECISION,
+                              low DOUBLE PRECISION,
+                              market_cap DOUBLE PRECISION,
+                              accepted BOOLEAN NOT NULL,
+                              fetched_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX price_quotes_block_height_idx ON price_quotes (block_height);
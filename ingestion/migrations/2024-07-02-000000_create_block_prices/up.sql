@@ -0,0 +1,8 @@
+-- USD price at each block's timestamp. A NULL price means the source had no data for it.
+CREATE TABLE block_prices (
+                              height INT PRIMARY KEY,
+                              btc_price DOUBLE PRECISION,
+                              source VARCHAR NOT NULL,
+                              priced_at TIMESTAMP NOT NULL,
+                              FOREIGN KEY (height) REFERENCES block_info (height)
+);
@@ -0,0 +1,7 @@
+DELETE FROM block_prices WHERE currency <> 'usd';
+ALTER TABLE block_prices DROP CONSTRAINT block_prices_pkey;
+ALTER TABLE block_prices ADD PRIMARY KEY (height);
+
+ALTER TABLE block_prices DROP COLUMN IF EXISTS currency;
+ALTER TABLE price_quotes DROP COLUMN IF EXISTS currency;
+ALTER TABLE offchain_data DROP COLUMN IF EXISTS currency;
@@ -0,0 +1,7 @@
+-- Everything stored so far was quoted in USD.
+ALTER TABLE offchain_data ADD COLUMN currency VARCHAR NOT NULL DEFAULT 'usd';
+ALTER TABLE price_quotes ADD COLUMN currency VARCHAR NOT NULL DEFAULT 'usd';
+ALTER TABLE block_prices ADD COLUMN currency VARCHAR NOT NULL DEFAULT 'usd';
+
+ALTER TABLE block_prices DROP CONSTRAINT block_prices_pkey;
+ALTER TABLE block_prices ADD PRIMARY KEY (height, currency);
@@ -0,0 +1,2 @@
+-- UTXO and address (dis)connects, reorg rollbacks and fee percentiles all select by height.
+CREATE INDEX transactions_block_height_idx ON transactions (block_height);
@@ -0,0 +1,4 @@
+UPDATE transaction_inputs SET value = 0 WHERE value IS NULL;
+ALTER TABLE transaction_inputs ALTER COLUMN value SET NOT NULL;
+UPDATE transactions SET fee = 0 WHERE fee IS NULL;
+ALTER TABLE transactions ALTER COLUMN fee SET NOT NULL;
@@ -0,0 +1,4 @@
+-- Sources without prevouts (block files) can only give an input's value and a transaction's fee
+-- once the spent output is stored; until then they are unknown rather than 0.
+ALTER TABLE transactions ALTER COLUMN fee DROP NOT NULL;
+ALTER TABLE transaction_inputs ALTER COLUMN value DROP NOT NULL;
@@ -0,0 +1,238 @@
+use bitcoin::address::NetworkUnchecked;
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager};
+use diesel::sql_types::{Array, BigInt, Integer, Varchar};
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::debug;
+
+use crate::error::{reject, with_connection, ApiError, CONNECTION_TIMEOUT};
+use crate::schema::addresses;
+use crate::PageQuery;
+
+// Per-address activity: one row per output paying the address and one per input spending such an
+// output. A spend is counted once both its input and the output it spends are stored, so spends by
+// the selected transactions and spends of their outputs by other stored transactions are picked up
+// in separate branches. Such an other transaction already counts towards the address's tx_count if
+// it pays the address or spends another of its outputs stored earlier, so then its id is left NULL
+// and COUNT(DISTINCT) skips it. `filter` is applied to the transactions' `column`.
+//
+// An input spends the earliest stored copy of its txid: the two coinbases repeated before BIP30
+// are stored twice, and matching both copies would count the spent output twice.
+fn activity_sql(column: &str, filter: &str) -> String {
+    format!(
+        "SELECT address,
+                SUM(funded_count)::BIGINT AS funded_txo_count,
+                SUM(funded_sum)::BIGINT AS funded_txo_sum,
+                SUM(spent_count)::BIGINT AS spent_txo_count,
+                SUM(spent_sum)::BIGINT AS spent_txo_sum,
+                COUNT(DISTINCT tx_id)::BIGINT AS tx_count,
+                MIN(height) AS first_seen_height,
+                MAX(height) AS last_seen_height
+         FROM (
+             SELECT o.address, o.transaction_id AS tx_id, t.block_height AS height,
+                    1 AS funded_count, o.value AS funded_sum, 0 AS spent_count, 0::BIGINT AS spent_sum
+             FROM transaction_outputs o
+             JOIN transactions t ON t.id = o.transaction_id
+             WHERE o.address <> '' AND t.{column} {filter}
+             UNION ALL
+             SELECT po.address, i.transaction_id, ti.block_height,
+                    0, 0::BIGINT, 1, po.value
+             FROM transaction_inputs i
+             JOIN transactions ti ON ti.id = i.transaction_id
+             JOIN transactions tpo ON tpo.hash = i.previous_output
+             JOIN transaction_outputs po ON po.transaction_id = tpo.id AND po.vout = i.previous_vout
+             WHERE po.address <> '' AND ti.{column} {filter}
+               AND NOT EXISTS (SELECT 1 FROM transactions e WHERE e.hash = tpo.hash AND e.block_height < tpo.block_height)
+             UNION ALL
+             SELECT po.address,
+                    CASE WHEN EXISTS (
+                             SELECT 1 FROM transaction_outputs so
+                             WHERE so.transaction_id = ti.id AND so.address = po.address
+                         ) OR EXISTS (
+                             SELECT 1 FROM transaction_inputs si
+                             JOIN transactions st ON st.hash = si.previous_output
+                             JOIN transaction_outputs sp ON sp.transaction_id = st.id AND sp.vout = si.previous_vout
+                             WHERE si.transaction_id = ti.id AND sp.address = po.address AND NOT (st.{column} {filter})
+                               AND NOT EXISTS (SELECT 1 FROM transactions e WHERE e.hash = st.hash AND e.block_height < st.block_height)
+                         ) THEN NULL ELSE i.transaction_id END,
+                    ti.block_height,
+                    0, 0::BIGINT, 1, po.value
+             FROM transaction_outputs po
+             JOIN transactions tpo ON tpo.id = po.transaction_id
+             JOIN transaction_inputs i ON i.previous_output = tpo.hash AND i.previous_vout = po.vout
+             JOIN transactions ti ON ti.id = i.transaction_id
+             WHERE po.address <> '' AND tpo.{column} {filter} AND NOT (ti.{column} {filter})
+               AND NOT EXISTS (SELECT 1 FROM transactions e WHERE e.hash = tpo.hash AND e.block_height < tpo.block_height)
+         ) activity
+         GROUP BY address"
+    )
+}
+
+// Folds the transactions with the given ids, just inserted, into the address index. Each
+// transaction is inserted only once, so re-ingesting a block, including one written before the
+// index existed and counted by its seed, adds nothing twice.
+pub fn connect_transactions(conn: &mut PgConnection, transaction_ids: &[i32]) -> QueryResult<()> {
+    if transaction_ids.is_empty() {
+        return Ok(());
+    }
+    diesel::sql_query(format!(
+        "INSERT INTO addresses (address, funded_txo_count, funded_txo_sum, spent_txo_count, spent_txo_sum,
+                                tx_count, first_seen_height, last_seen_height)
+         {}
+         ON CONFLICT (address) DO UPDATE SET
+             funded_txo_count = addresses.funded_txo_count + EXCLUDED.funded_txo_count,
+             funded_txo_sum = addresses.funded_txo_sum + EXCLUDED.funded_txo_sum,
+             spent_txo_count = addresses.spent_txo_count + EXCLUDED.spent_txo_count,
+             spent_txo_sum = addresses.spent_txo_sum + EXCLUDED.spent_txo_sum,
+             tx_count = addresses.tx_count + EXCLUDED.tx_count,
+             first_seen_height = LEAST(addresses.first_seen_height, EXCLUDED.first_seen_height),
+             last_seen_height = GREATEST(addresses.last_seen_height, EXCLUDED.last_seen_height)",
+        activity_sql("id", "= ANY($1)")
+    ))
+    .bind::<Array<Integer>, _>(transaction_ids)
+    .execute(conn)?;
+    Ok(())
+}
+
+// Takes every block above `fork_height` back out of the address index. Must run before their
+// transactions are deleted.
+pub fn disconnect_blocks_above(conn: &mut PgConnection, fork_height: i32) -> QueryResult<()> {
+    diesel::sql_query(format!(
+        "UPDATE addresses a SET
+             funded_txo_count = a.funded_txo_count - d.funded_txo_count,
+             funded_txo_sum = a.funded_txo_sum - d.funded_txo_sum,
+             spent_txo_count = a.spent_txo_count - d.spent_txo_count,
+             spent_txo_sum = a.spent_txo_sum - d.spent_txo_sum,
+             tx_count = a.tx_count - d.tx_count
+         FROM ({}) d
+         WHERE a.address = d.address",
+        activity_sql("block_height", "> $1")
+    ))
+    .bind::<Integer, _>(fork_height)
+    .execute(conn)?;
+
+    diesel::delete(addresses::table.filter(addresses::first_seen_height.gt(fork_height))).execute(conn)?;
+
+    diesel::sql_query(
+        "UPDATE addresses a SET last_seen_height = (
+             SELECT MAX(height) FROM (
+                 SELECT t.block_height AS height
+                 FROM transaction_outputs o
+                 JOIN transactions t ON t.id = o.transaction_id
+                 WHERE o.address = a.address AND t.block_height <= $1
+                 UNION ALL
+                 SELECT ti.block_height
+                 FROM transaction_outputs o
+                 JOIN transactions t ON t.id = o.transaction_id
+                 JOIN transaction_inputs i ON i.previous_output = t.hash AND i.previous_vout = o.vout
+                 JOIN transactions ti ON ti.id = i.transaction_id
+                 WHERE o.address = a.address AND ti.block_height <= $1
+                   AND NOT EXISTS (SELECT 1 FROM transactions e WHERE e.hash = t.hash AND e.block_height < t.block_height)
+             ) seen
+         )
+         WHERE a.last_seen_height > $1",
+    )
+    .bind::<Integer, _>(fork_height)
+    .execute(conn)?;
+
+    Ok(())
+}
+
+#[derive(Queryable, Serialize)]
+pub struct AddressStats {
+    pub address: String,
+    pub funded_txo_count: i64,
+    pub funded_txo_sum: i64,
+    pub spent_txo_count: i64,
+    pub spent_txo_sum: i64,
+    pub tx_count: i64,
+    pub first_seen_height: i32,
+    pub last_seen_height: i32,
+}
+
+#[derive(QueryableByName, Serialize)]
+pub struct AddressTransaction {
+    #[diesel(sql_type = Varchar)]
+    pub txid: String,
+    #[diesel(sql_type = Integer)]
+    pub block_height: i32,
+    #[diesel(sql_type = BigInt)]
+    pub time: i64,
+    #[diesel(sql_type = BigInt)]
+    pub received: i64,
+    #[diesel(sql_type = BigInt)]
+    pub sent: i64,
+}
+
+#[derive(Serialize)]
+pub struct AddressDetail {
+    #[serde(flatten)]
+    pub stats: AddressStats,
+    pub balance: i64,
+    pub page: i64,
+    pub per_page: i64,
+    pub transactions: Vec<AddressTransaction>,
+}
+
+pub async fn handle_get_address(
+    pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>,
+    address: String,
+    query: PageQuery,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    debug!(address, "Handling get address...");
+    // Any network is accepted; the stored addresses are whatever the source reported.
+    if address.parse::<bitcoin::Address<NetworkUnchecked>>().is_err() {
+        return Err(reject(ApiError::BadRequest(format!("Invalid address {}", address))));
+    }
+    let page = query.page();
+    let per_page = query.per_page();
+    let (stats, transactions) = with_connection(pool, CONNECTION_TIMEOUT, move |conn| {
+        let stats: Option<AddressStats> = addresses::table.find(&address).first(conn).optional()?;
+        let Some(stats) = stats else {
+            return Err(ApiError::NotFound(format!("Address {} not found", address)));
+        };
+
+        // Newest first: every transaction that pays the address or spends one of its outputs,
+        // with the amounts it moved in and out of the address.
+        let transactions: Vec<AddressTransaction> = diesel::sql_query(
+            "SELECT t.hash AS txid, t.block_height, t.time,
+                    COALESCE(SUM(h.received), 0)::BIGINT AS received,
+                    COALESCE(SUM(h.sent), 0)::BIGINT AS sent
+             FROM (
+                 SELECT o.transaction_id AS tx_id, o.value AS received, 0::BIGINT AS sent
+                 FROM transaction_outputs o
+                 WHERE o.address = $1
+                 UNION ALL
+                 SELECT i.transaction_id, 0::BIGINT, o.value
+                 FROM transaction_outputs o
+                 JOIN transactions pt ON pt.id = o.transaction_id
+                 JOIN transaction_inputs i ON i.previous_output = pt.hash AND i.previous_vout = o.vout
+                 WHERE o.address = $1
+                   AND NOT EXISTS (SELECT 1 FROM transactions e WHERE e.hash = pt.hash AND e.block_height < pt.block_height)
+             ) h
+             JOIN transactions t ON t.id = h.tx_id
+             GROUP BY t.id, t.hash, t.block_height, t.time
+             ORDER BY t.block_height DESC, t.id DESC
+             LIMIT $2 OFFSET $3",
+        )
+        .bind::<Varchar, _>(&address)
+        .bind::<BigInt, _>(per_page)
+        .bind::<BigInt, _>((page - 1) * per_page)
+        .load(conn)?;
+
+        Ok((stats, transactions))
+    })
+    .await
+    .map_err(reject)?;
+
+    let detail = AddressDetail {
+        balance: stats.funded_txo_sum - stats.spent_txo_sum,
+        stats,
+        page,
+        per_page,
+        transactions,
+    };
+
+    Ok(warp::reply::json(&detail))
+}
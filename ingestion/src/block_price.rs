@@ -0,0 +1,190 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager};
+use std::error::Error;
+use std::sync::Arc;
+use tokio::time::{self, Duration};
+use tracing::{error, info};
+
+use crate::price::{self, PriceHistory};
+use crate::schema::{block_info, block_prices};
+use crate::supervisor::{Shutdown, WorkerResult};
+
+// Blocks priced per pass, newest first.
+const BATCH_SIZE: i64 = 500;
+// Blocks are grouped so one history request covers at most this many seconds, which keeps
+// CoinGecko on hourly (or finer) points.
+const MAX_RANGE: i64 = 24 * 60 * 60;
+// A block only gets a price from points at most this far from its timestamp; daily series are
+// the coarsest expected.
+const MAX_PRICE_GAP: i64 = 24 * 60 * 60;
+
+#[derive(Insertable)]
+#[diesel(table_name = block_prices)]
+struct NewBlockPrice {
+    height: i32,
+    currency: String,
+    btc_price: Option<f64>,
+    source: String,
+    priced_at: NaiveDateTime,
+}
+
+// Ties every stored block to the BTC price at its own timestamp in each tracked currency,
+// covering new blocks as they arrive and backfilled ones alike. Blocks the history has no price
+// for are recorded with a NULL price so they are not asked for again until a different
+// offchain.history is configured.
+pub async fn fetch_and_store_block_prices(
+    pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>,
+    history: Arc<dyn PriceHistory>,
+    currencies: Vec<String>,
+    poll_interval: Duration,
+    mut shutdown: Shutdown,
+) -> WorkerResult {
+    let mut interval = time::interval(poll_interval);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.wait() => break,
+        }
+
+        for currency in &currencies {
+            if let Err(e) = price_blocks(&pool, history.as_ref(), currency).await {
+                error!(currency = %currency, error = %e, "Error pricing blocks");
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn price_blocks(
+    pool: &r2d2::Pool<ConnectionManager<PgConnection>>,
+    history: &dyn PriceHistory,
+    currency: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let settled = block_prices::table
+        .filter(block_prices::height.eq(block_info::height))
+        .filter(block_prices::currency.eq(currency))
+        .filter(block_prices::btc_price.is_not_null().or(block_prices::source.eq(history.name())));
+    let blocks: Vec<(i32, i64)> = block_info::table
+        .filter(diesel::dsl::not(diesel::dsl::exists(settled)))
+        .select((block_info::height, block_info::timestamp))
+        .order(block_info::height.desc())
+        .limit(BATCH_SIZE)
+        .load::<(i32, NaiveDateTime)>(&mut pool.get()?)?
+        .into_iter()
+        .map(|(height, timestamp)| (height, timestamp.and_utc().timestamp()))
+        .collect();
+
+    for group in group_blocks(&blocks) {
+        let from = group.iter().map(|(_, time)| *time).min().unwrap_or_default() - MAX_PRICE_GAP;
+        let to = group.iter().map(|(_, time)| *time).max().unwrap_or_default() + MAX_PRICE_GAP;
+        let points = history.prices_between(currency, from, to).await?;
+
+        let rows = price_rows(&group, &points, currency, history.name(), Utc::now().naive_utc());
+
+        // A rollback may have removed some of these blocks meanwhile; the foreign key on height
+        // then fails the insert and the survivors are picked up on the next pass.
+        diesel::insert_into(block_prices::table)
+            .values(&rows)
+            .on_conflict((block_prices::height, block_prices::currency))
+            .do_update()
+            .set((
+                block_prices::btc_price.eq(diesel::upsert::excluded(block_prices::btc_price)),
+                block_prices::source.eq(diesel::upsert::excluded(block_prices::source)),
+                block_prices::priced_at.eq(diesel::upsert::excluded(block_prices::priced_at)),
+            ))
+            .execute(&mut pool.get()?)?;
+
+        info!(
+            from_height = group.last().map_or(0, |(height, _)| *height),
+            to_height = group[0].0,
+            currency = %currency,
+            source = history.name(),
+            "Priced blocks"
+        );
+    }
+
+    Ok(())
+}
+
+// Splits (height, unix time) blocks, newest first, into runs that one history request can cover.
+fn group_blocks(blocks: &[(i32, i64)]) -> Vec<Vec<(i32, i64)>> {
+    let mut groups: Vec<Vec<(i32, i64)>> = Vec::new();
+    for &(height, time) in blocks {
+        match groups.last_mut() {
+            Some(group) if (group[0].1 - time).abs() <= MAX_RANGE => group.push((height, time)),
+            _ => groups.push(vec![(height, time)]),
+        }
+    }
+    groups
+}
+
+// One row per block. Blocks `points` has no price for get a NULL price under `source`, which
+// settles them for that source.
+fn price_rows(
+    group: &[(i32, i64)],
+    points: &[(i64, f64)],
+    currency: &str,
+    source: &str,
+    priced_at: NaiveDateTime,
+) -> Vec<NewBlockPrice> {
+    group
+        .iter()
+        .map(|(height, time)| NewBlockPrice {
+            height: *height,
+            currency: currency.to_string(),
+            btc_price: price::price_at(points, *time, MAX_PRICE_GAP),
+            source: source.to_string(),
+            priced_at,
+        })
+        .collect()
+}
+
+// Prices depend on the block's timestamp, so they go when the block is rolled back.
+pub fn disconnect_blocks_above(conn: &mut PgConnection, fork_height: i32) -> QueryResult<()> {
+    diesel::delete(block_prices::table.filter(block_prices::height.gt(fork_height))).execute(conn)?;
+    Ok(())
+}
+
+pub fn price_for_block(conn: &mut PgConnection, height: i32, currency: &str) -> QueryResult<Option<f64>> {
+    Ok(block_prices::table
+        .find((height, currency))
+        .select(block_prices::btc_price)
+        .first::<Option<f64>>(conn)
+        .optional()?
+        .flatten())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: i64 = 60 * 60;
+
+    #[test]
+    fn groups_blocks_a_day_apart_at_most() {
+        let blocks = [(300, 10 * MAX_RANGE), (299, 10 * MAX_RANGE - HOUR), (298, 9 * MAX_RANGE), (100, MAX_RANGE)];
+        assert_eq!(
+            group_blocks(&blocks),
+            vec![vec![(300, 10 * MAX_RANGE), (299, 10 * MAX_RANGE - HOUR), (298, 9 * MAX_RANGE)], vec![(100, MAX_RANGE)]]
+        );
+        assert!(group_blocks(&[]).is_empty());
+    }
+
+    #[test]
+    fn settles_blocks_without_a_price_with_a_null_price() {
+        let points = [(10 * MAX_PRICE_GAP, 100.0), (10 * MAX_PRICE_GAP + HOUR, 200.0)];
+        let group = [(3, 12 * MAX_PRICE_GAP), (2, 10 * MAX_PRICE_GAP + HOUR / 2), (1, 5 * MAX_PRICE_GAP)];
+        let priced_at = Utc::now().naive_utc();
+
+        let rows = price_rows(&group, &points, "eur", "coingecko", priced_at);
+        let prices: Vec<(i32, Option<f64>)> = rows.iter().map(|row| (row.height, row.btc_price)).collect();
+        assert_eq!(prices, vec![(3, None), (2, Some(150.0)), (1, None)]);
+        assert!(rows.iter().all(|row| row.currency == "eur" && row.source == "coingecko" && row.priced_at == priced_at));
+
+        // An empty history still settles every block for its source.
+        let rows = price_rows(&group, &[], "eur", "csv", priced_at);
+        assert!(rows.iter().all(|row| row.btc_price.is_none() && row.source == "csv"));
+    }
+}
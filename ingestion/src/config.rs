@@ -0,0 +1,723 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::source::RpcAuth;
+
+// Price providers the offchain worker knows how to build.
+pub const PRICE_PROVIDERS: &[&str] = &["coingecko", "kraken", "coinbase", "bitstamp"];
+
+// One setting: its key in the config file (`[section]` then `name`), the environment variable
+// that overrides it and its default. The command line flag is the key with dots and underscores
+// turned into dashes, e.g. api.cors_origins is --api-cors-origins.
+struct Setting {
+    key: &'static str,
+    env: &'static str,
+    default: Option<&'static str>,
+}
+
+const fn setting(key: &'static str, env: &'static str, default: Option<&'static str>) -> Setting {
+    Setting { key, env, default }
+}
+
+const SETTINGS: &[Setting] = &[
+    setting("network", "BITCOIN_NETWORK", Some("main")),
+    setting("log.filter", "RUST_LOG", Some("info")),
+    setting("log.format", "LOG_FORMAT", Some("text")),
+    setting("database.url", "DATABASE_URL", None),
+    setting("database.pool_size", "DATABASE_POOL_SIZE", Some("10")),
+    setting("api.bind", "BIND_ADDRESS", Some("0.0.0.0:8000")),
+    setting("api.cors_origins", "CORS_ORIGINS", Some("*")),
+    setting("source.kind", "BLOCK_SOURCE", Some("esplora")),
+    setting("source.esplora_urls", "ESPLORA_URLS", Some("https://blockstream.info/api")),
+    setting("source.bitcoind_url", "BITCOIND_URL", Some("http://127.0.0.1:8332")),
+    setting("source.bitcoind_cookie_file", "BITCOIND_COOKIE_FILE", None),
+    setting("source.bitcoind_user", "BITCOIND_USER", None),
+    setting("source.bitcoind_password", "BITCOIND_PASSWORD", None),
+    setting("source.blocks_dir", "BLOCKS_DIR", None),
+    setting("backfill.from", "BACKFILL_FROM", None),
+    setting("backfill.to", "BACKFILL_TO", None),
+    setting("intervals.tip_secs", "TIP_POLL_SECS", Some("10")),
+    setting("intervals.mempool_secs", "MEMPOOL_POLL_SECS", Some("10")),
+    setting("intervals.offchain_secs", "OFFCHAIN_POLL_SECS", Some("10")),
+    setting("intervals.block_prices_secs", "BLOCK_PRICE_POLL_SECS", Some("10")),
+    setting("intervals.retry_secs", "RETRY_DELAY_SECS", Some("10")),
+    setting("offchain.providers", "PRICE_PROVIDERS", Some("coingecko,kraken,coinbase,bitstamp")),
+    setting("offchain.currencies", "FIAT_CURRENCIES", Some("usd")),
+    setting("offchain.max_deviation", "PRICE_MAX_DEVIATION", Some("0.02")),
+    setting("offchain.history", "PRICE_HISTORY", Some("coingecko")),
+    setting("offchain.history_csv", "PRICE_HISTORY_CSV", None),
+    setting("offchain.coingecko_url", "COINGECKO_URL", Some("https://api.coingecko.com/api/v3")),
+    setting("offchain.kraken_url", "KRAKEN_URL", Some("https://api.kraken.com")),
+    setting("offchain.coinbase_url", "COINBASE_URL", Some("https://api.exchange.coinbase.com")),
+    setting("offchain.bitstamp_url", "BITSTAMP_URL", Some("https://www.bitstamp.net")),
+    setting("fees.estimate_blocks", "FEE_ESTIMATE_BLOCKS", Some("6")),
+    setting("health.max_lag_blocks", "READY_MAX_LAG_BLOCKS", Some("3")),
+    setting("health.max_offchain_age_secs", "READY_MAX_OFFCHAIN_AGE_SECS", Some("300")),
+    setting("http.timeout_secs", "HTTP_TIMEOUT_SECS", Some("30")),
+    setting("http.max_retries", "HTTP_MAX_RETRIES", Some("3")),
+    setting("http.backoff_ms", "HTTP_BACKOFF_MS", Some("500")),
+    setting("http.max_backoff_ms", "HTTP_MAX_BACKOFF_MS", Some("30000")),
+    setting("http.max_per_host", "HTTP_MAX_PER_HOST", Some("4")),
+    setting("supervisor.restart_backoff_ms", "WORKER_RESTART_BACKOFF_MS", Some("1000")),
+    setting("supervisor.max_restart_backoff_ms", "WORKER_MAX_RESTART_BACKOFF_MS", Some("60000")),
+    setting("supervisor.shutdown_timeout_secs", "SHUTDOWN_TIMEOUT_SECS", Some("30")),
+];
+
+// Environment variables that were renamed, as (old, new). The old name is still read when the new
+// one is unset, with a warning.
+const RENAMED_ENV: &[(&str, &str)] = &[("ESPLORA_URL", "ESPLORA_URLS")];
+
+pub struct Config {
+    // Deprecated settings that were used, to log once logging is set up.
+    pub warnings: Vec<String>,
+    pub network: bitcoin::Network,
+    pub log: LogConfig,
+    pub database: DatabaseConfig,
+    pub api: ApiConfig,
+    pub source: SourceConfig,
+    // (start height, optional end height) of the backfill to run, if any.
+    pub backfill: Option<(i32, Option<i32>)>,
+    pub intervals: IntervalConfig,
+    pub offchain: OffchainConfig,
+    pub fees: FeesConfig,
+    pub health: HealthConfig,
+    pub http: HttpConfig,
+    pub supervisor: SupervisorConfig,
+}
+
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+pub struct LogConfig {
+    // An EnvFilter directive such as "info" or "ingestion=debug,warp=warn".
+    pub filter: String,
+    pub format: LogFormat,
+}
+
+pub struct DatabaseConfig {
+    pub url: String,
+    pub pool_size: u32,
+}
+
+pub struct ApiConfig {
+    pub bind: SocketAddr,
+    // Allowed CORS origins; "*" allows any.
+    pub cors_origins: Vec<String>,
+}
+
+pub enum SourceConfig {
+    Esplora { urls: Vec<String> },
+    Bitcoind { url: String, auth: RpcAuth },
+    BlkFiles { blocks_dir: String },
+}
+
+pub struct IntervalConfig {
+    pub tip: Duration,
+    pub mempool: Duration,
+    pub offchain: Duration,
+    pub block_prices: Duration,
+    // Wait before retrying a failed backfill step.
+    pub retry: Duration,
+}
+
+pub enum PriceHistoryConfig {
+    CoinGecko,
+    Csv(String),
+}
+
+pub struct OffchainConfig {
+    pub providers: Vec<String>,
+    pub currencies: Vec<String>,
+    pub max_deviation: f64,
+    pub history: PriceHistoryConfig,
+    pub coingecko_url: String,
+    pub kraken_url: String,
+    pub coinbase_url: String,
+    pub bitstamp_url: String,
+}
+
+pub struct FeesConfig {
+    pub estimate_blocks: i32,
+}
+
+// Thresholds for GET /ready.
+pub struct HealthConfig {
+    pub max_lag_blocks: i64,
+    pub max_offchain_age: Duration,
+}
+
+pub struct HttpConfig {
+    pub timeout: Duration,
+    pub max_retries: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    pub max_per_host: usize,
+}
+
+pub struct SupervisorConfig {
+    // Delay before restarting a crashed worker, doubled on each crash up to max_restart_backoff.
+    pub restart_backoff: Duration,
+    pub max_restart_backoff: Duration,
+    // How long SIGTERM waits for in-flight requests and block writes before exiting anyway.
+    pub shutdown_timeout: Duration,
+}
+
+// Everything wrong with the configuration, so it can all be fixed in one go.
+#[derive(Debug)]
+pub struct ConfigError {
+    problems: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, problem) in self.problems.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "  {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl ConfigError {
+    fn single(problem: String) -> Self {
+        ConfigError { problems: vec![problem] }
+    }
+}
+
+fn flag_name(key: &str) -> String {
+    format!("--{}", key.replace(['.', '_'], "-"))
+}
+
+pub fn usage() -> String {
+    let mut text = String::from(
+        "Usage: ingestion [--config FILE] [--<setting> VALUE]...\n\n\
+         Settings are read from the TOML file given with --config (or CONFIG_FILE), then from\n\
+         environment variables, then from command line flags; later layers win.\n\n",
+    );
+    for setting in SETTINGS {
+        text.push_str(&format!(
+            "  {:<32} {:<24} {}\n",
+            flag_name(setting.key),
+            setting.env,
+            setting.default.unwrap_or("-")
+        ));
+    }
+    text
+}
+
+impl Config {
+    // Defaults, then the TOML file, then environment variables, then command line flags.
+    pub fn load(args: &[String]) -> Result<Config, ConfigError> {
+        Config::load_with_env(args, |name| env::var(name).ok())
+    }
+
+    fn load_with_env(args: &[String], env: impl Fn(&str) -> Option<String>) -> Result<Config, ConfigError> {
+        let mut values: HashMap<&'static str, (String, String)> = HashMap::new();
+        for setting in SETTINGS {
+            if let Some(default) = setting.default {
+                values.insert(setting.key, (default.to_string(), "default".to_string()));
+            }
+        }
+
+        let flags = parse_flags(args)?;
+
+        let file = flags
+            .iter()
+            .find(|(flag, _)| flag == "--config")
+            .map(|(_, path)| path.clone())
+            .or_else(|| env("CONFIG_FILE").filter(|path| !path.is_empty()));
+        if let Some(path) = &file {
+            let text = fs::read_to_string(path)
+                .map_err(|e| ConfigError::single(format!("could not read config file {}: {}", path, e)))?;
+            let table: toml::Table = text
+                .parse()
+                .map_err(|e| ConfigError::single(format!("could not parse config file {}: {}", path, e)))?;
+            let mut entries = Vec::new();
+            flatten_toml("", &table, &mut entries).map_err(|e| ConfigError::single(format!("{}: {}", path, e)))?;
+
+            let mut problems = Vec::new();
+            for (key, value) in entries {
+                match SETTINGS.iter().find(|setting| setting.key == key) {
+                    Some(setting) => {
+                        values.insert(setting.key, (value, path.clone()));
+                    }
+                    None => problems.push(format!("{}: unknown setting {}", path, key)),
+                }
+            }
+            if !problems.is_empty() {
+                return Err(ConfigError { problems });
+            }
+        }
+
+        let mut warnings = Vec::new();
+        for setting in SETTINGS {
+            let set = |name: &str| env(name).filter(|value| !value.is_empty());
+            if let Some(value) = set(setting.env) {
+                values.insert(setting.key, (value, setting.env.to_string()));
+                continue;
+            }
+            for (old, _) in RENAMED_ENV.iter().filter(|(_, new)| *new == setting.env) {
+                if let Some(value) = set(old) {
+                    warnings.push(format!("{} is deprecated, use {} instead", old, setting.env));
+                    values.insert(setting.key, (value, old.to_string()));
+                }
+            }
+        }
+
+        let mut problems = Vec::new();
+        for (flag, value) in flags {
+            if flag == "--config" {
+                continue;
+            }
+            match SETTINGS.iter().find(|setting| flag_name(setting.key) == flag) {
+                Some(setting) => {
+                    values.insert(setting.key, (value, flag));
+                }
+                None => problems.push(format!("unknown flag {}", flag)),
+            }
+        }
+        if !problems.is_empty() {
+            return Err(ConfigError { problems });
+        }
+
+        let mut config = Resolver { values, problems: Vec::new() }.resolve()?;
+        config.warnings = warnings;
+        Ok(config)
+    }
+}
+
+// Splits `--flag value` and `--flag=value` pairs.
+fn parse_flags(args: &[String]) -> Result<Vec<(String, String)>, ConfigError> {
+    let mut flags = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            return Err(ConfigError::single(format!("unexpected argument {}", arg)));
+        }
+        match arg.split_once('=') {
+            Some((flag, value)) => flags.push((flag.to_string(), value.to_string())),
+            None => match args.next() {
+                Some(value) if !value.starts_with("--") => flags.push((arg.clone(), value.clone())),
+                _ => return Err(ConfigError::single(format!("{} needs a value", arg))),
+            },
+        }
+    }
+    Ok(flags)
+}
+
+// Flattens a TOML document into (section.key, value) pairs. Arrays come back comma separated,
+// like the environment variables.
+fn flatten_toml(prefix: &str, table: &toml::Table, entries: &mut Vec<(String, String)>) -> Result<(), String> {
+    for (name, value) in table {
+        let key = if prefix.is_empty() { name.clone() } else { format!("{}.{}", prefix, name) };
+        match value {
+            toml::Value::Table(table) => flatten_toml(&key, table, entries)?,
+            toml::Value::Array(items) => {
+                let items = items.iter().map(|item| scalar(&key, item)).collect::<Result<Vec<_>, _>>()?;
+                entries.push((key, items.join(",")));
+            }
+            other => {
+                let value = scalar(&key, other)?;
+                entries.push((key, value));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn scalar(key: &str, value: &toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(text) => Ok(text.clone()),
+        toml::Value::Integer(number) => Ok(number.to_string()),
+        toml::Value::Float(number) => Ok(number.to_string()),
+        toml::Value::Boolean(flag) => Ok(flag.to_string()),
+        _ => Err(format!("{} must be a string, number, boolean or a list of those", key)),
+    }
+}
+
+// Turns the layered raw values into a Config, collecting every problem along the way.
+struct Resolver {
+    values: HashMap<&'static str, (String, String)>,
+    problems: Vec<String>,
+}
+
+impl Resolver {
+    fn string(&self, key: &str) -> Option<String> {
+        self.values
+            .get(key)
+            .map(|(value, _)| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    }
+
+    fn required(&mut self, key: &str, why: &str) -> String {
+        self.string(key).unwrap_or_else(|| {
+            let env = SETTINGS.iter().find(|setting| setting.key == key).map_or("", |setting| setting.env);
+            self.problems.push(format!("{} ({}) must be set {}", key, env, why));
+            String::new()
+        })
+    }
+
+    fn list(&self, key: &str) -> Vec<String> {
+        self.string(key)
+            .unwrap_or_default()
+            .split(',')
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect()
+    }
+
+    fn invalid(&mut self, key: &str, message: impl fmt::Display) {
+        let origin = self.values.get(key).map_or("", |(_, origin)| origin.as_str());
+        self.problems.push(format!("{} (from {}): {}", key, origin, message));
+    }
+
+    fn parsed<T: FromStr>(&mut self, key: &str) -> Option<T>
+    where
+        T::Err: fmt::Display,
+    {
+        let value = self.string(key)?;
+        match value.parse() {
+            Ok(parsed) => Some(parsed),
+            Err(e) => {
+                self.invalid(key, format_args!("invalid value {:?}: {}", value, e));
+                None
+            }
+        }
+    }
+
+    // A parsed number that must be at least `min`.
+    fn at_least<T: FromStr + PartialOrd + fmt::Display + Copy>(&mut self, key: &str, min: T) -> T
+    where
+        T::Err: fmt::Display,
+    {
+        match self.parsed::<T>(key) {
+            Some(value) if value >= min => value,
+            Some(value) => {
+                self.invalid(key, format_args!("{} is below the minimum of {}", value, min));
+                min
+            }
+            None => min,
+        }
+    }
+
+    fn secs(&mut self, key: &str) -> Duration {
+        Duration::from_secs(self.at_least(key, 1u64))
+    }
+
+    fn url(&mut self, key: &str, value: String) -> String {
+        match reqwest::Url::parse(&value) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+            Ok(_) => self.invalid(key, format_args!("{} is not an http(s) URL", value)),
+            Err(e) => self.invalid(key, format_args!("invalid URL {}: {}", value, e)),
+        }
+        value
+    }
+
+    fn resolve(mut self) -> Result<Config, ConfigError> {
+        let network_name = self.string("network").unwrap_or_default();
+        let network = bitcoin::Network::from_core_arg(&network_name).unwrap_or_else(|_| {
+            self.invalid("network", "expected main, test, testnet4, signet or regtest");
+            bitcoin::Network::Bitcoin
+        });
+
+        let filter = self.string("log.filter").unwrap_or_default();
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&filter) {
+            self.invalid("log.filter", format_args!("invalid filter {:?}: {}", filter, e));
+        }
+        let format = match self.string("log.format").unwrap_or_default().as_str() {
+            "text" => LogFormat::Text,
+            "json" => LogFormat::Json,
+            other => {
+                self.invalid("log.format", format_args!("unknown log format {:?}; expected text or json", other));
+                LogFormat::Text
+            }
+        };
+        let log = LogConfig { filter, format };
+
+        let database = DatabaseConfig {
+            url: self.required("database.url", "to reach Postgres"),
+            pool_size: self.at_least("database.pool_size", 1),
+        };
+
+        let bind = self.parsed("api.bind").unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 8000)));
+        let cors_origins = self.list("api.cors_origins");
+        for origin in &cors_origins {
+            let valid = origin == "*"
+                || reqwest::Url::parse(origin)
+                    .map(|url| url.origin().ascii_serialization() == origin.trim_end_matches('/'))
+                    .unwrap_or(false);
+            if !valid {
+                self.invalid("api.cors_origins", format_args!("{} is not * or an origin such as https://example.com", origin));
+            }
+        }
+        let api = ApiConfig { bind, cors_origins };
+
+        let source = match self.string("source.kind").unwrap_or_default().as_str() {
+            "esplora" => {
+                let urls = self.list("source.esplora_urls");
+                if urls.is_empty() {
+                    self.invalid("source.esplora_urls", "at least one Esplora URL is needed");
+                }
+                let urls = urls.into_iter().map(|url| self.url("source.esplora_urls", url)).collect();
+                SourceConfig::Esplora { urls }
+            }
+            "bitcoind" => {
+                let url = self.required("source.bitcoind_url", "for the bitcoind source");
+                let url = self.url("source.bitcoind_url", url);
+                let auth = match self.string("source.bitcoind_cookie_file") {
+                    Some(path) => RpcAuth::CookieFile(path),
+                    None => RpcAuth::UserPass(
+                        self.required("source.bitcoind_user", "when no cookie file is given"),
+                        self.required("source.bitcoind_password", "when no cookie file is given"),
+                    ),
+                };
+                SourceConfig::Bitcoind { url, auth }
+            }
+            "blkfiles" => {
+                let blocks_dir = self.required("source.blocks_dir", "for the blkfiles source");
+                if !blocks_dir.is_empty() && !Path::new(&blocks_dir).is_dir() {
+                    self.invalid("source.blocks_dir", format_args!("{} is not a directory", blocks_dir));
+                }
+                SourceConfig::BlkFiles { blocks_dir }
+            }
+            other => {
+                self.invalid("source.kind", format_args!("unknown block source {:?}; expected esplora, bitcoind or blkfiles", other));
+                SourceConfig::Esplora { urls: Vec::new() }
+            }
+        };
+
+        let backfill = match self.string("backfill.from") {
+            Some(from) => {
+                let start_height = if from.eq_ignore_ascii_case("genesis") { Some(0) } else { self.parsed::<i32>("backfill.from") };
+                let end_height = self.parsed::<i32>("backfill.to");
+                match (start_height, end_height) {
+                    (Some(start), _) if start < 0 => {
+                        self.invalid("backfill.from", "must be a block height or \"genesis\"");
+                        None
+                    }
+                    (Some(start), Some(end)) if end < start => {
+                        self.invalid("backfill.to", format_args!("{} is below backfill.from {}", end, start));
+                        None
+                    }
+                    (Some(start), end) => Some((start, end)),
+                    (None, _) => None,
+                }
+            }
+            None => None,
+        };
+
+        let intervals = IntervalConfig {
+            tip: self.secs("intervals.tip_secs"),
+            mempool: self.secs("intervals.mempool_secs"),
+            offchain: self.secs("intervals.offchain_secs"),
+            block_prices: self.secs("intervals.block_prices_secs"),
+            retry: self.secs("intervals.retry_secs"),
+        };
+
+        let providers: Vec<String> = self.list("offchain.providers").iter().map(|name| name.to_lowercase()).collect();
+        if providers.is_empty() {
+            self.invalid("offchain.providers", "at least one price provider is needed");
+        }
+        for name in &providers {
+            if !PRICE_PROVIDERS.contains(&name.as_str()) {
+                self.invalid("offchain.providers", format_args!("unknown price provider {:?}; expected one of {}", name, PRICE_PROVIDERS.join(", ")));
+            }
+        }
+
+        let currencies: Vec<String> = self.list("offchain.currencies").iter().map(|currency| currency.to_lowercase()).collect();
+        if currencies.is_empty() {
+            self.invalid("offchain.currencies", "at least one currency is needed");
+        }
+        for currency in &currencies {
+            if !currency.chars().all(|c| c.is_ascii_alphabetic()) {
+                self.invalid("offchain.currencies", format_args!("{:?} is not a currency code", currency));
+            }
+        }
+
+        let max_deviation = match self.parsed::<f64>("offchain.max_deviation") {
+            Some(value) if value.is_finite() && value > 0.0 => value,
+            Some(value) => {
+                self.invalid("offchain.max_deviation", format_args!("{} must be a positive fraction", value));
+                0.02
+            }
+            None => 0.02,
+        };
+
+        let history = match self.string("offchain.history").unwrap_or_default().as_str() {
+            "coingecko" => PriceHistoryConfig::CoinGecko,
+            "csv" => PriceHistoryConfig::Csv(self.required("offchain.history_csv", "when offchain.history is csv")),
+            other => {
+                self.invalid("offchain.history", format_args!("unknown price history {:?}; expected coingecko or csv", other));
+                PriceHistoryConfig::CoinGecko
+            }
+        };
+
+        let mut provider_url = |key: &str| {
+            let url = self.required(key, "for its price provider");
+            self.url(key, url)
+        };
+        let offchain = OffchainConfig {
+            providers,
+            currencies,
+            max_deviation,
+            history,
+            coingecko_url: provider_url("offchain.coingecko_url"),
+            kraken_url: provider_url("offchain.kraken_url"),
+            coinbase_url: provider_url("offchain.coinbase_url"),
+            bitstamp_url: provider_url("offchain.bitstamp_url"),
+        };
+
+        let fees = FeesConfig {
+            estimate_blocks: self.at_least("fees.estimate_blocks", 1),
+        };
+
+        let health = HealthConfig {
+            max_lag_blocks: self.at_least("health.max_lag_blocks", 0),
+            max_offchain_age: self.secs("health.max_offchain_age_secs"),
+        };
+
+        let http = HttpConfig {
+            timeout: self.secs("http.timeout_secs"),
+            max_retries: self.at_least("http.max_retries", 0),
+            backoff: Duration::from_millis(self.at_least("http.backoff_ms", 0)),
+            max_backoff: Duration::from_millis(self.at_least("http.max_backoff_ms", 0)),
+            max_per_host: self.at_least("http.max_per_host", 1),
+        };
+        if http.max_backoff < http.backoff {
+            self.invalid("http.max_backoff_ms", "must not be below http.backoff_ms");
+        }
+
+        let supervisor = SupervisorConfig {
+            restart_backoff: Duration::from_millis(self.at_least("supervisor.restart_backoff_ms", 1)),
+            max_restart_backoff: Duration::from_millis(self.at_least("supervisor.max_restart_backoff_ms", 1)),
+            shutdown_timeout: self.secs("supervisor.shutdown_timeout_secs"),
+        };
+        if supervisor.max_restart_backoff < supervisor.restart_backoff {
+            self.invalid("supervisor.max_restart_backoff_ms", "must not be below supervisor.restart_backoff_ms");
+        }
+
+        if !self.problems.is_empty() {
+            return Err(ConfigError { problems: self.problems });
+        }
+
+        Ok(Config { warnings: Vec::new(), network, log, database, api, source, backfill, intervals, offchain, fees, health, http, supervisor })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(flags: &[&str]) -> Vec<String> {
+        flags.iter().map(|flag| flag.to_string()).collect()
+    }
+
+    fn load(flags: &[&str], vars: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let mut env: HashMap<String, String> = HashMap::from([("DATABASE_URL".to_string(), "postgres://localhost/db".to_string())]);
+        env.extend(vars.iter().map(|(name, value)| (name.to_string(), value.to_string())));
+        Config::load_with_env(&args(flags), |name| env.get(name).cloned())
+    }
+
+    fn config_file(name: &str, contents: &str) -> String {
+        let path = env::temp_dir().join(format!("ingestion-config-{}-{}.toml", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path.display().to_string()
+    }
+
+    fn problems(result: Result<Config, ConfigError>) -> Vec<String> {
+        match result {
+            Ok(_) => panic!("expected the configuration to be rejected"),
+            Err(e) => e.problems,
+        }
+    }
+
+    #[test]
+    fn flags_override_env_which_overrides_the_file() {
+        let file = config_file("precedence", "[intervals]\ntip_secs = 20\nmempool_secs = 20\noffchain_secs = 20\n");
+        let env = [("TIP_POLL_SECS", "30"), ("MEMPOOL_POLL_SECS", "30")];
+        let config = load(&["--config", &file, "--intervals-tip-secs", "40"], &env).unwrap();
+        assert_eq!(config.intervals.tip, Duration::from_secs(40));
+        assert_eq!(config.intervals.mempool, Duration::from_secs(30));
+        assert_eq!(config.intervals.offchain, Duration::from_secs(20));
+        assert_eq!(config.intervals.retry, Duration::from_secs(10));
+
+        // CONFIG_FILE stands in for --config.
+        let config = load(&[], &[("CONFIG_FILE", &file)]).unwrap();
+        assert_eq!(config.intervals.tip, Duration::from_secs(20));
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn parses_lists_from_env_flags_and_file_arrays() {
+        let config = load(&["--offchain-providers=kraken, Coinbase,"], &[("ESPLORA_URLS", " https://a.example/api ,,https://b.example/api")]).unwrap();
+        let SourceConfig::Esplora { urls } = &config.source else { panic!("expected the Esplora source") };
+        assert_eq!(urls, &["https://a.example/api", "https://b.example/api"]);
+        assert_eq!(config.offchain.providers, ["kraken", "coinbase"]);
+
+        let file = config_file("lists", "[offchain]\ncurrencies = [\"usd\", \"EUR\"]\n");
+        let config = load(&["--config", &file], &[]).unwrap();
+        assert_eq!(config.offchain.currencies, ["usd", "eur"]);
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn reads_the_deprecated_esplora_url() {
+        let config = load(&[], &[("ESPLORA_URL", "https://old.example/api")]).unwrap();
+        let SourceConfig::Esplora { urls } = &config.source else { panic!("expected the Esplora source") };
+        assert_eq!(urls, &["https://old.example/api"]);
+        assert_eq!(config.warnings, ["ESPLORA_URL is deprecated, use ESPLORA_URLS instead"]);
+
+        let config = load(&[], &[("ESPLORA_URL", "https://old.example/api"), ("ESPLORA_URLS", "https://new.example/api")]).unwrap();
+        let SourceConfig::Esplora { urls } = &config.source else { panic!("expected the Esplora source") };
+        assert_eq!(urls, &["https://new.example/api"]);
+        assert!(config.warnings.is_empty());
+    }
+
+    #[test]
+    fn reports_every_invalid_value() {
+        let env = [
+            ("BITCOIN_NETWORK", "moon"),
+            ("DATABASE_POOL_SIZE", "0"),
+            ("LOG_FORMAT", "xml"),
+            ("ESPLORA_URLS", "ftp://mirror.example"),
+            ("PRICE_PROVIDERS", "kraken,oracle"),
+            ("FIAT_CURRENCIES", "us$"),
+            ("PRICE_MAX_DEVIATION", "-1"),
+            ("HTTP_BACKOFF_MS", "100"),
+            ("HTTP_MAX_BACKOFF_MS", "10"),
+            ("TIP_POLL_SECS", "soon"),
+        ];
+        let problems = problems(load(&[], &env));
+        // The two backoff settings make one problem between them.
+        assert_eq!(problems.len(), env.len() - 1, "{:#?}", problems);
+        for key in ["network", "database.pool_size", "log.format", "source.esplora_urls", "offchain.providers"] {
+            assert!(problems.iter().any(|problem| problem.starts_with(key)), "no problem reported for {}", key);
+        }
+        assert!(problems.iter().any(|problem| problem.contains("(from DATABASE_POOL_SIZE)")));
+    }
+
+    #[test]
+    fn rejects_missing_and_unknown_settings() {
+        assert_eq!(problems(Config::load_with_env(&[], |_| None)), ["database.url (DATABASE_URL) must be set to reach Postgres"]);
+
+        assert_eq!(problems(load(&["--no-such-flag", "1"], &[])), ["unknown flag --no-such-flag"]);
+        assert_eq!(problems(load(&["--api-bind"], &[])), ["--api-bind needs a value"]);
+        assert_eq!(problems(load(&["stray"], &[])), ["unexpected argument stray"]);
+
+        let file = config_file("unknown", "[api]\nport = 80\n");
+        assert_eq!(problems(load(&["--config", &file], &[])), [format!("{}: unknown setting api.port", file)]);
+        fs::remove_file(file).unwrap();
+    }
+}
@@ -0,0 +1,176 @@
+use diesel::r2d2::{self, ConnectionManager};
+use diesel::result::DatabaseErrorKind;
+use diesel::PgConnection;
+use serde::Serialize;
+use std::convert::Infallible;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, warn};
+use warp::http::StatusCode;
+use warp::Rejection;
+
+// Errors the API handlers answer with. Handlers turn them into rejections with `reject` and
+// `handle_rejection` renders every rejection, warp's own included, as a JSON {code, message}.
+#[derive(Debug)]
+pub enum ApiError {
+    NotFound(String),
+    BadRequest(String),
+    // Postgres could not be reached; worth retrying.
+    Unavailable(String),
+    Internal(String),
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) => "not_found",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unavailable(_) => "unavailable",
+            ApiError::Internal(_) => "internal",
+        }
+    }
+
+    // What the client is told. Database and internal failures are only described in the log.
+    fn public_message(&self) -> String {
+        match self {
+            ApiError::NotFound(message) | ApiError::BadRequest(message) => message.clone(),
+            ApiError::Unavailable(_) => "Database unavailable, try again later".to_string(),
+            ApiError::Internal(_) => "Internal server error".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::NotFound(message)
+            | ApiError::BadRequest(message)
+            | ApiError::Unavailable(message)
+            | ApiError::Internal(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl warp::reject::Reject for ApiError {}
+
+impl From<r2d2::PoolError> for ApiError {
+    fn from(e: r2d2::PoolError) -> Self {
+        ApiError::Unavailable(format!("Failed to get connection from pool: {}", e))
+    }
+}
+
+impl From<diesel::result::Error> for ApiError {
+    fn from(e: diesel::result::Error) -> Self {
+        match e {
+            diesel::result::Error::NotFound => ApiError::NotFound("Not found".to_string()),
+            diesel::result::Error::DatabaseError(
+                DatabaseErrorKind::ClosedConnection | DatabaseErrorKind::UnableToSendCommand,
+                _,
+            ) => ApiError::Unavailable(e.to_string()),
+            e => ApiError::Internal(e.to_string()),
+        }
+    }
+}
+
+// API requests give up on a pooled connection after this long and answer 503, rather than holding
+// the client for r2d2's 30 second default while Postgres is down or every connection is busy.
+pub const CONNECTION_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Checks out a connection, waiting at most `timeout`, and runs `query` with it. Both happen on the
+// blocking pool: diesel and r2d2 are synchronous, and a request stuck waiting on Postgres must not
+// hold up a runtime thread that other requests and the workers need. Failing to get a connection
+// in time is ApiError::Unavailable.
+pub async fn with_connection<T, F>(
+    pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>,
+    timeout: Duration,
+    query: F,
+) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut PgConnection) -> Result<T, ApiError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || {
+        let mut conn = pool.get_timeout(timeout)?;
+        query(&mut conn)
+    })
+    .await
+    .map_err(|e| ApiError::Internal(format!("Database task failed: {}", e)))?
+}
+
+// For `.map_err(reject)?` in handlers returning warp::Rejection.
+pub fn reject(e: impl Into<ApiError>) -> Rejection {
+    warp::reject::custom(e.into())
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    code: &'static str,
+    message: String,
+}
+
+pub async fn handle_rejection(rejection: Rejection) -> Result<impl warp::Reply, Infallible> {
+    let (status, code, message) = if let Some(e) = rejection.find::<ApiError>() {
+        match e {
+            ApiError::Unavailable(_) => warn!(error = %e, "Request failed"),
+            ApiError::Internal(_) => error!(error = %e, "Request failed"),
+            _ => debug!(error = %e, "Request rejected"),
+        }
+        (e.status(), e.code(), e.public_message())
+    } else if rejection.is_not_found() {
+        (StatusCode::NOT_FOUND, "not_found", "Not found".to_string())
+    } else if let Some(e) = rejection.find::<warp::reject::InvalidQuery>() {
+        (StatusCode::BAD_REQUEST, "bad_request", e.to_string())
+    } else if let Some(e) = rejection.find::<warp::cors::CorsForbidden>() {
+        (StatusCode::FORBIDDEN, "forbidden", e.to_string())
+    } else if let Some(e) = rejection.find::<warp::reject::MethodNotAllowed>() {
+        (StatusCode::METHOD_NOT_ALLOWED, "method_not_allowed", e.to_string())
+    } else {
+        error!(rejection = ?rejection, "Unhandled rejection");
+        (StatusCode::INTERNAL_SERVER_ERROR, "internal", "Internal server error".to_string())
+    };
+
+    Ok(warp::reply::with_status(warp::reply::json(&ErrorBody { code, message }), status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Instant;
+    use warp::Reply;
+
+    #[tokio::test]
+    async fn answers_unavailable_soon_when_postgres_is_down() {
+        // Nothing listens on port 1, so every connection attempt fails.
+        let manager = ConnectionManager::<PgConnection>::new("postgres://postgres@127.0.0.1:1/none");
+        let pool = Arc::new(r2d2::Pool::builder().build_unchecked(manager));
+
+        // The test runtime has a single thread, so a request waiting on the pool there would hold
+        // up this timer until it gave up.
+        let started = Instant::now();
+        let request = tokio::spawn(crate::utxo::handle_get_utxo_set(pool));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(started.elapsed() < CONNECTION_TIMEOUT, "runtime blocked for {:?}", started.elapsed());
+
+        let rejection = match request.await.unwrap() {
+            Ok(_) => panic!("expected the request to fail"),
+            Err(rejection) => rejection,
+        };
+        assert!(started.elapsed() < CONNECTION_TIMEOUT * 2, "took {:?}", started.elapsed());
+        assert!(matches!(rejection.find::<ApiError>(), Some(ApiError::Unavailable(_))));
+
+        let response = handle_rejection(rejection).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,192 @@
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager};
+use diesel::sql_types::{BigInt, Double, Integer};
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::debug;
+
+use crate::error::{reject, with_connection, CONNECTION_TIMEOUT};
+use crate::price::median;
+
+// Virtual size a block can hold.
+const BLOCK_VSIZE: i64 = 1_000_000;
+// Bitcoin Core's default minimum relay fee, in sat/vB.
+const MIN_FEE_RATE: f64 = 1.0;
+
+// Confirmation targets in blocks for the 30 minute, 1 hour and economy estimates.
+const HALF_HOUR_BLOCKS: i64 = 3;
+const HOUR_BLOCKS: i64 = 6;
+const ECONOMY_BLOCKS: i64 = 144;
+
+// Unconfirmed vsize at each fee rate (sat/vB, rounded to 0.1), highest rate first.
+#[derive(QueryableByName)]
+struct FeeRateBucket {
+    #[diesel(sql_type = Double)]
+    fee_rate: f64,
+    #[diesel(sql_type = BigInt)]
+    vsize: i64,
+}
+
+// Fee rates paid in one stored block: the 10th percentile is roughly the cheapest rate that still
+// got in, the median is what a typical transaction paid.
+#[derive(QueryableByName)]
+struct BlockFeeRates {
+    #[diesel(sql_type = Double)]
+    low: f64,
+    #[diesel(sql_type = Double)]
+    median: f64,
+}
+
+// All rates are in sat/vB.
+#[derive(Serialize)]
+pub struct RecommendedFees {
+    pub next_block: f64,
+    pub half_hour: f64,
+    pub hour: f64,
+    pub economy: f64,
+}
+
+// Rate needed to be among the first `blocks` blocks' worth of the mempool when it is mined
+// highest fee rate first. None when the whole mempool fits in fewer blocks than that.
+fn mempool_fee_rate(histogram: &[FeeRateBucket], blocks: i64) -> Option<f64> {
+    let mut cumulative_vsize = 0;
+    for bucket in histogram {
+        cumulative_vsize += bucket.vsize;
+        if cumulative_vsize >= blocks * BLOCK_VSIZE {
+            return Some(bucket.fee_rate);
+        }
+    }
+    None
+}
+
+// The mempool decides each target. Recent blocks add a floor to the next-block target, since
+// paying less than what the last blocks' marginal transactions paid rarely gets into the next
+// one, and stand in for the mempool entirely when nothing is being polled.
+fn estimate(histogram: &[FeeRateBucket], recent: &[BlockFeeRates]) -> RecommendedFees {
+    let recent_low = median(recent.iter().map(|block| block.low).collect());
+    let recent_median = median(recent.iter().map(|block| block.median).collect());
+
+    let (next_block, half_hour, hour, economy) = if histogram.is_empty() {
+        (
+            recent_median.unwrap_or(MIN_FEE_RATE),
+            recent_low.unwrap_or(MIN_FEE_RATE),
+            recent_low.unwrap_or(MIN_FEE_RATE),
+            MIN_FEE_RATE,
+        )
+    } else {
+        let target = |blocks| mempool_fee_rate(histogram, blocks).unwrap_or(MIN_FEE_RATE);
+        (
+            target(1).max(recent_low.unwrap_or(MIN_FEE_RATE)),
+            target(HALF_HOUR_BLOCKS),
+            target(HOUR_BLOCKS),
+            target(ECONOMY_BLOCKS),
+        )
+    };
+
+    // Round up to 0.1 sat/vB and keep the targets ordered from fastest to cheapest.
+    let round = |rate: f64| (rate.max(MIN_FEE_RATE) * 10.0).ceil() / 10.0;
+    let next_block = round(next_block);
+    let half_hour = round(half_hour).min(next_block);
+    let hour = round(hour).min(half_hour);
+    let economy = round(economy).min(hour);
+
+    RecommendedFees { next_block, half_hour, hour, economy }
+}
+
+pub async fn handle_get_recommended_fees(
+    pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>,
+    // How many of the latest stored blocks are looked at.
+    recent_blocks: i32,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    debug!("Handling get recommended fees...");
+    let (histogram, recent) = with_connection(pool, CONNECTION_TIMEOUT, move |conn| {
+        let histogram: Vec<FeeRateBucket> = diesel::sql_query(
+            "SELECT ROUND(fee::NUMERIC / vsize, 1)::FLOAT8 AS fee_rate, SUM(vsize)::BIGINT AS vsize
+             FROM mempool_transactions
+             WHERE confirmed_height IS NULL AND vsize > 0
+             GROUP BY 1
+             ORDER BY 1 DESC",
+        )
+        .load(conn)?;
+
+        // Transactions without fee or size data (coinbases, sources without prevouts, rows stored
+        // before weights were recorded) are left out; blocks with none left drop out entirely.
+        let recent: Vec<BlockFeeRates> = diesel::sql_query(
+            "SELECT PERCENTILE_CONT(0.1) WITHIN GROUP (ORDER BY t.fee::FLOAT8 / t.vsize) AS low,
+                    PERCENTILE_CONT(0.5) WITHIN GROUP (ORDER BY t.fee::FLOAT8 / t.vsize) AS median
+             FROM transactions t
+             WHERE t.block_height > (SELECT MAX(height) FROM block_info WHERE complete) - $1
+               AND t.fee > 0
+               AND t.vsize > 0
+             GROUP BY t.block_height",
+        )
+        .bind::<Integer, _>(recent_blocks)
+        .load(conn)?;
+
+        Ok((histogram, recent))
+    })
+    .await
+    .map_err(reject)?;
+
+    Ok(warp::reply::json(&estimate(&histogram, &recent)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(buckets: &[(f64, i64)]) -> Vec<FeeRateBucket> {
+        buckets.iter().map(|&(fee_rate, vsize)| FeeRateBucket { fee_rate, vsize }).collect()
+    }
+
+    fn recent(blocks: &[(f64, f64)]) -> Vec<BlockFeeRates> {
+        blocks.iter().map(|&(low, median)| BlockFeeRates { low, median }).collect()
+    }
+
+    // Mempool histogram, recent blocks' (low, median) and the expected next block, half hour, hour
+    // and economy rates.
+    type Case<'a> = (&'a [(f64, i64)], &'a [(f64, f64)], [f64; 4]);
+
+    #[test]
+    fn estimates_from_mempool_and_recent_blocks() {
+        let cases: &[Case] = &[
+            // Nothing to go on.
+            (&[], &[], [1.0, 1.0, 1.0, 1.0]),
+            // No mempool: recent blocks stand in, with medians across an odd and an even count.
+            (&[], &[(2.0, 10.0), (4.0, 20.0), (3.0, 30.0)], [20.0, 3.0, 3.0, 1.0]),
+            (&[], &[(2.0, 10.0), (4.0, 20.0)], [15.0, 3.0, 3.0, 1.0]),
+            // Targets are where the cumulative vsize reaches 1, 3 and 6 blocks.
+            (&[(50.0, 600_000), (20.0, 600_000), (10.0, 2_000_000), (5.0, 3_000_000)], &[], [20.0, 10.0, 5.0, 1.0]),
+            // Exactly one block's worth at the top rate.
+            (&[(7.0, 1_000_000), (3.0, 1_000_000)], &[], [7.0, 1.0, 1.0, 1.0]),
+            // A mempool that fits in the next block is floored by what recent blocks' cheapest
+            // transactions paid.
+            (&[(30.0, 100_000)], &[(8.0, 12.0)], [8.0, 1.0, 1.0, 1.0]),
+            // But the floor never lowers the mempool's own rate.
+            (&[(30.0, 1_000_000)], &[(8.0, 12.0)], [30.0, 1.0, 1.0, 1.0]),
+            // Rates round up to 0.1 sat/vB.
+            (&[(12.34, 1_000_000)], &[], [12.4, 1.0, 1.0, 1.0]),
+            // Rates below the minimum relay fee are raised to it.
+            (&[(0.5, 10_000_000)], &[], [1.0, 1.0, 1.0, 1.0]),
+        ];
+
+        for (buckets, blocks, expected) in cases {
+            let fees = estimate(&histogram(buckets), &recent(blocks));
+            assert_eq!(
+                [fees.next_block, fees.half_hour, fees.hour, fees.economy],
+                *expected,
+                "mempool {:?}, recent blocks {:?}",
+                buckets,
+                blocks
+            );
+        }
+    }
+
+    #[test]
+    fn mempool_fee_rate_needs_enough_vsize() {
+        let buckets = histogram(&[(40.0, 400_000), (20.0, 400_000), (10.0, 400_000)]);
+        assert_eq!(mempool_fee_rate(&buckets, 1), Some(10.0));
+        assert_eq!(mempool_fee_rate(&buckets, 2), None);
+        assert_eq!(mempool_fee_rate(&[], 1), None);
+    }
+}
@@ -0,0 +1,168 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager};
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::time::Duration;
+use tracing::{debug, warn};
+use warp::http::StatusCode;
+
+use crate::config::HealthConfig;
+use crate::error::with_connection;
+use crate::metrics;
+use crate::schema::{block_info, offchain_data};
+use crate::supervisor::{Supervisor, WorkerState, WorkerStatus};
+
+// Probes wait less for a connection than API requests do, so an orchestrator hears back well
+// within its own probe timeout.
+const PROBE_CONNECTION_TIMEOUT: Duration = Duration::from_millis(1_000);
+
+#[derive(Serialize)]
+pub struct Health {
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub workers: Vec<WorkerStatus>,
+}
+
+#[derive(Serialize, Default)]
+pub struct Readiness {
+    pub ready: bool,
+    // Why the service is not ready; empty when it is.
+    pub reasons: Vec<String>,
+    pub last_height: Option<i32>,
+    pub last_hash: Option<String>,
+    pub upstream_height: Option<i64>,
+    pub lag_blocks: Option<i64>,
+    pub offchain_age_secs: Option<i64>,
+    pub workers: Vec<WorkerStatus>,
+}
+
+// Alive as long as the process answers and Postgres is reachable through the pool. Crashed
+// workers are restarted by the supervisor rather than by failing this check, so their status is
+// reported without affecting it.
+pub async fn handle_get_health(
+    pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>,
+    supervisor: Arc<Supervisor>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    debug!("Handling get health...");
+    let check = with_connection(pool, PROBE_CONNECTION_TIMEOUT, |conn| {
+        Ok(diesel::sql_query("SELECT 1").execute(conn)?)
+    })
+    .await;
+
+    let workers = supervisor.statuses();
+    let (health, status) = match check {
+        Ok(_) => (Health { status: "ok", error: None, workers }, StatusCode::OK),
+        Err(e) => {
+            warn!(error = %e, "Health check failed");
+            (Health { status: "unavailable", error: Some(e.to_string()), workers }, StatusCode::SERVICE_UNAVAILABLE)
+        }
+    };
+    Ok(warp::reply::with_status(warp::reply::json(&health), status))
+}
+
+// Ready once the stored chain is within `max_lag_blocks` of the source's tip and the newest
+// offchain data is no older than `max_offchain_age`. The upstream tip is the one the tip worker
+// last saw, so a probe never waits on the block source. A worker waiting to be restarted after a
+// crash also makes the service unready, since the data it maintains has stopped moving.
+pub async fn handle_get_ready(
+    pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>,
+    config: Arc<HealthConfig>,
+    supervisor: Arc<Supervisor>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    debug!("Handling get ready...");
+    let check = with_connection(pool, PROBE_CONNECTION_TIMEOUT, move |conn| Ok(readiness(conn, &config)?)).await;
+    let mut readiness = match check {
+        Ok(readiness) => readiness,
+        Err(e) => {
+            warn!(error = %e, "Readiness check failed");
+            Readiness { reasons: vec![format!("database unavailable: {}", e)], ..Readiness::default() }
+        }
+    };
+    readiness.workers = supervisor.statuses();
+    for worker in &readiness.workers {
+        if worker.state == WorkerState::Restarting {
+            readiness.reasons.push(format!("{} worker crashed and is restarting", worker.name));
+        }
+    }
+    readiness.ready = readiness.reasons.is_empty();
+
+    let status = if readiness.ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    Ok(warp::reply::with_status(warp::reply::json(&readiness), status))
+}
+
+fn readiness(conn: &mut PgConnection, config: &HealthConfig) -> QueryResult<Readiness> {
+
+    let last_block: Option<(i32, Option<String>)> = block_info::table
+        .filter(block_info::complete.eq(true))
+        .select((block_info::height, block_info::hash))
+        .order(block_info::height.desc())
+        .first(conn)
+        .optional()?;
+    let latest_offchain: Option<NaiveDateTime> = offchain_data::table
+        .select(diesel::dsl::max(offchain_data::timestamp))
+        .first(conn)?;
+
+    // The gauge starts at 0 and only ever holds a real tip once the source has answered.
+    let upstream_height = Some(metrics::UPSTREAM_TIP_HEIGHT.get()).filter(|height| *height > 0);
+    let last_height = last_block.as_ref().map(|(height, _)| *height);
+    let lag_blocks = upstream_height.zip(last_height).map(|(upstream, last)| (upstream - last as i64).max(0));
+    let offchain_age_secs = latest_offchain.map(|timestamp| (Utc::now().naive_utc() - timestamp).num_seconds().max(0));
+
+    let mut reasons = Vec::new();
+    match (last_height, upstream_height, lag_blocks) {
+        (None, _, _) => reasons.push("no block ingested yet".to_string()),
+        (_, None, _) => reasons.push("upstream tip not known yet".to_string()),
+        (_, _, Some(lag)) if lag > config.max_lag_blocks => {
+            reasons.push(format!("{} blocks behind the upstream tip, more than {}", lag, config.max_lag_blocks))
+        }
+        _ => {}
+    }
+    match offchain_age_secs {
+        None => reasons.push("no offchain data fetched yet".to_string()),
+        Some(age) if age > config.max_offchain_age.as_secs() as i64 => {
+            reasons.push(format!("offchain data is {}s old, more than {}s", age, config.max_offchain_age.as_secs()))
+        }
+        _ => {}
+    }
+
+    Ok(Readiness {
+        ready: reasons.is_empty(),
+        reasons,
+        last_height,
+        last_hash: last_block.and_then(|(_, hash)| hash),
+        upstream_height,
+        lag_blocks,
+        offchain_age_secs,
+        workers: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SupervisorConfig;
+    use tokio::time::Instant;
+    use warp::Reply;
+
+    #[tokio::test]
+    async fn probes_answer_quickly_when_postgres_is_down() {
+        // Nothing listens on port 1, so every connection attempt fails.
+        let manager = ConnectionManager::<PgConnection>::new("postgres://postgres@127.0.0.1:1/none");
+        let pool = Arc::new(r2d2::Pool::builder().build_unchecked(manager));
+        let supervisor = Arc::new(Supervisor::new(SupervisorConfig {
+            restart_backoff: Duration::from_millis(1),
+            max_restart_backoff: Duration::from_millis(1),
+            shutdown_timeout: Duration::from_secs(1),
+        }));
+        let config = Arc::new(HealthConfig { max_lag_blocks: 3, max_offchain_age: Duration::from_secs(300) });
+
+        let started = Instant::now();
+        let health = handle_get_health(Arc::clone(&pool), Arc::clone(&supervisor)).await.unwrap().into_response();
+        assert_eq!(health.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let ready = handle_get_ready(pool, config, supervisor).await.unwrap().into_response();
+        assert_eq!(ready.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(started.elapsed() < PROBE_CONNECTION_TIMEOUT * 4, "took {:?}", started.elapsed());
+    }
+}
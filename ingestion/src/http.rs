@@ -0,0 +1,348 @@
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+use tokio::time::{self, Duration, Instant};
+use tracing::{debug, warn};
+
+use crate::config::HttpConfig;
+use crate::metrics;
+
+// Longest we will honour a Retry-After header for; anything longer fails the call instead.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(120);
+
+#[derive(Debug)]
+pub enum HttpError {
+    Timeout { url: String },
+    Connect { url: String, source: reqwest::Error },
+    RateLimited { url: String, retry_after: Option<Duration> },
+    Status { url: String, status: StatusCode },
+    Decode { url: String, source: reqwest::Error },
+    Request { url: String, source: reqwest::Error },
+}
+
+impl HttpError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            HttpError::Timeout { .. } => "timeout",
+            HttpError::Connect { .. } => "connect",
+            HttpError::RateLimited { .. } => "rate_limited",
+            HttpError::Status { .. } => "status",
+            HttpError::Decode { .. } => "decode",
+            HttpError::Request { .. } => "request",
+        }
+    }
+
+    fn from_reqwest(url: &str, source: reqwest::Error) -> Self {
+        let url = url.to_string();
+        if source.is_timeout() {
+            HttpError::Timeout { url }
+        } else if source.is_connect() {
+            HttpError::Connect { url, source }
+        } else if source.is_decode() || source.is_body() {
+            HttpError::Decode { url, source }
+        } else {
+            HttpError::Request { url, source }
+        }
+    }
+
+    fn is_retryable(&self) -> bool {
+        match self {
+            HttpError::Timeout { .. } | HttpError::Connect { .. } | HttpError::RateLimited { .. } => true,
+            HttpError::Status { status, .. } => is_retryable_status(*status),
+            HttpError::Decode { .. } | HttpError::Request { .. } => false,
+        }
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Timeout { url } => write!(f, "{} timed out", url),
+            HttpError::Connect { url, source } => write!(f, "could not connect to {}: {}", url, source),
+            HttpError::RateLimited { url, retry_after: Some(delay) } => {
+                write!(f, "{} is rate limited, retry after {}s", url, delay.as_secs())
+            }
+            HttpError::RateLimited { url, retry_after: None } => write!(f, "{} is rate limited", url),
+            HttpError::Status { url, status } => write!(f, "{} returned HTTP {}", url, status),
+            HttpError::Decode { url, source } => write!(f, "could not read response from {}: {}", url, source),
+            HttpError::Request { url, source } => write!(f, "request to {} failed: {}", url, source),
+        }
+    }
+}
+
+impl Error for HttpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HttpError::Connect { source, .. } | HttpError::Decode { source, .. } | HttpError::Request { source, .. } => {
+                Some(source)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+fn retry_after(response: &Response) -> Option<Duration> {
+    parse_retry_after(response.headers().get(RETRY_AFTER)?.to_str().ok()?, Utc::now())
+}
+
+// Retry-After is either a number of seconds or an HTTP date; a date already past means no delay.
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&Utc) - now).to_std().unwrap_or(Duration::ZERO))
+}
+
+fn host_of(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| format!("{}:{}", host, url.port_or_known_default().unwrap_or(0))))
+        .unwrap_or_default()
+}
+
+// The one HTTP client every upstream call goes through. Requests time out, transient failures
+// (timeouts, connection errors, 429 and 502-504) are retried with exponential backoff and full
+// jitter, a 429's Retry-After is honoured, and each host gets a bounded number of requests in
+// flight. Failures that survive the retries are logged and counted per host and kind in
+// ingestion_upstream_errors_total.
+pub struct HttpClient {
+    client: reqwest::Client,
+    max_retries: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    per_host_limit: usize,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl HttpClient {
+    pub fn new(config: &HttpConfig) -> Self {
+        let client = reqwest::Client::builder()
+            // Coinbase rejects requests without a User-Agent.
+            .user_agent(concat!("ingestion/", env!("CARGO_PKG_VERSION")))
+            .connect_timeout(Duration::from_secs(10))
+            .timeout(config.timeout)
+            .build()
+            .expect("Failed to build HTTP client");
+
+        HttpClient {
+            client,
+            max_retries: config.max_retries,
+            base_delay: config.backoff,
+            max_delay: config.max_backoff,
+            per_host_limit: config.max_per_host,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn host_permits(&self, host: &str) -> Arc<Semaphore> {
+        let mut hosts = self.hosts.lock().unwrap();
+        Arc::clone(hosts.entry(host.to_string()).or_insert_with(|| Arc::new(Semaphore::new(self.per_host_limit))))
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self.base_delay.saturating_mul(2u32.saturating_pow(attempt)).min(self.max_delay);
+        ceiling.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+
+    fn fail(&self, error: HttpError) -> HttpError {
+        let url = match &error {
+            HttpError::Timeout { url }
+            | HttpError::Connect { url, .. }
+            | HttpError::RateLimited { url, .. }
+            | HttpError::Status { url, .. }
+            | HttpError::Decode { url, .. }
+            | HttpError::Request { url, .. } => url,
+        };
+        self.record_failure(&host_of(url), &error);
+        error
+    }
+
+    fn record_failure(&self, host: &str, error: &HttpError) {
+        let failures = metrics::UPSTREAM_ERRORS.with_label_values(&[host, error.kind()]);
+        failures.inc();
+        warn!(host, kind = error.kind(), failures = failures.get(), error = %error, "Upstream request failed");
+    }
+
+    // Sends the request built by `build`, retrying transient failures. Any other response is
+    // returned as is, whatever its status, so callers can handle 404s or JSON error bodies.
+    #[tracing::instrument(name = "upstream", skip_all, fields(url = %url))]
+    pub async fn send(
+        &self,
+        url: &str,
+        build: impl Fn(&reqwest::Client) -> RequestBuilder,
+    ) -> Result<Response, HttpError> {
+        let host = host_of(url);
+        let permits = self.host_permits(&host);
+        let mut attempt = 0;
+        let started = Instant::now();
+
+        loop {
+            let result = {
+                let _permit = permits.acquire().await.expect("host semaphore closed");
+                let _timer = metrics::UPSTREAM_REQUEST_DURATION.with_label_values(&[&host]).start_timer();
+                build(&self.client).send().await
+            };
+
+            let error = match result {
+                Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => HttpError::RateLimited {
+                    url: url.to_string(),
+                    retry_after: retry_after(&response),
+                },
+                Ok(response) if is_retryable_status(response.status()) => HttpError::Status {
+                    url: url.to_string(),
+                    status: response.status(),
+                },
+                Ok(response) => {
+                    debug!(status = %response.status(), attempts = attempt + 1, elapsed_ms = started.elapsed().as_millis() as u64, "Upstream response");
+                    return Ok(response);
+                }
+                Err(e) => HttpError::from_reqwest(url, e),
+            };
+
+            let delay = match &error {
+                HttpError::RateLimited { retry_after: Some(delay), .. } if *delay > MAX_RETRY_AFTER => None,
+                HttpError::RateLimited { retry_after: Some(delay), .. } => Some(*delay),
+                _ => Some(self.backoff(attempt)),
+            };
+            match delay {
+                Some(delay) if error.is_retryable() && attempt < self.max_retries => {
+                    attempt += 1;
+                    metrics::UPSTREAM_RETRIES.with_label_values(&[&host]).inc();
+                    warn!(attempt, delay_ms = delay.as_millis() as u64, error = %error, "Retrying upstream request");
+                    time::sleep(delay).await;
+                }
+                _ => {
+                    self.record_failure(&host, &error);
+                    return Err(error);
+                }
+            }
+        }
+    }
+
+    pub async fn get(&self, url: &str) -> Result<Response, HttpError> {
+        self.send(url, |client| client.get(url)).await
+    }
+
+    pub async fn get_text(&self, url: &str) -> Result<String, HttpError> {
+        let response = self.checked(url, self.get(url).await?)?;
+        response.text().await.map_err(|e| self.fail(HttpError::from_reqwest(url, e)))
+    }
+
+    pub async fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T, HttpError> {
+        let response = self.checked(url, self.get(url).await?)?;
+        self.json(url, response).await
+    }
+
+    pub async fn get_json_with_query<T: DeserializeOwned>(&self, url: &str, query: &[(&str, &str)]) -> Result<T, HttpError> {
+        let response = self.checked(url, self.send(url, |client| client.get(url).query(query)).await?)?;
+        self.json(url, response).await
+    }
+
+    pub async fn json<T: DeserializeOwned>(&self, url: &str, response: Response) -> Result<T, HttpError> {
+        response.json::<T>().await.map_err(|e| self.fail(HttpError::Decode { url: url.to_string(), source: e }))
+    }
+
+    // Turns a non-2xx response into an error.
+    pub fn checked(&self, url: &str, response: Response) -> Result<Response, HttpError> {
+        if response.status().is_success() {
+            return Ok(response);
+        }
+        Err(self.fail(HttpError::Status { url: url.to_string(), status: response.status() }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use warp::Filter;
+
+    #[test]
+    fn parses_retry_after_seconds_and_dates() {
+        let now = DateTime::parse_from_rfc2822("Wed, 21 Oct 2015 07:28:00 GMT").unwrap().with_timezone(&Utc);
+        assert_eq!(parse_retry_after("120", now), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 ", now), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now), Some(Duration::from_secs(30)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:27:00 GMT", now), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("-5", now), None);
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn backoff_stays_under_its_ceiling() {
+        let client = test_util::http_client();
+        for attempt in [0, 1, 2, 10, 40, u32::MAX] {
+            let ceiling = (Duration::from_millis(1) * 2u32.saturating_pow(attempt)).min(Duration::from_millis(5));
+            for _ in 0..100 {
+                assert!(client.backoff(attempt) <= ceiling, "attempt {}", attempt);
+            }
+        }
+    }
+
+    // Answers with `status` and `retry_after` for the first `failures` requests and 200 after that.
+    fn flaky(status: u16, retry_after: &'static str, failures: usize) -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+        let route = warp::any().map(move || {
+            let status = if counter.fetch_add(1, Ordering::SeqCst) < failures { status } else { 200 };
+            let reply = warp::reply::with_status("{}", warp::http::StatusCode::from_u16(status).unwrap());
+            warp::reply::with_header(reply, "retry-after", retry_after)
+        });
+        (test_util::serve(route), requests)
+    }
+
+    #[tokio::test]
+    async fn retries_rate_limits_and_unavailable_hosts() {
+        for status in [429, 503] {
+            let (url, requests) = flaky(status, "0", 2);
+            let response = test_util::http_client().get(&url).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(requests.load(Ordering::SeqCst), 3, "HTTP {}", status);
+        }
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let (url, requests) = flaky(503, "0", usize::MAX);
+        let error = test_util::http_client().get(&url).await.unwrap_err();
+        assert!(matches!(error, HttpError::Status { status: StatusCode::SERVICE_UNAVAILABLE, .. }));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn fails_at_once_when_retry_after_is_too_long() {
+        let (url, requests) = flaky(429, "121", usize::MAX);
+        let error = test_util::http_client().get(&url).await.unwrap_err();
+        assert!(matches!(error, HttpError::RateLimited { retry_after: Some(delay), .. } if delay == Duration::from_secs(121)));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn returns_not_found_without_retrying() {
+        let (url, requests) = flaky(404, "0", usize::MAX);
+        let response = test_util::http_client().get(&url).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        let error = test_util::http_client().get_json::<serde_json::Value>(&url).await.unwrap_err();
+        assert!(matches!(error, HttpError::Status { status: StatusCode::NOT_FOUND, .. }));
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+}
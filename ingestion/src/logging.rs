@@ -0,0 +1,14 @@
+use tracing_subscriber::EnvFilter;
+
+use crate::config::{LogConfig, LogFormat};
+
+// Installs the global subscriber. Text output prefixes each event with its enclosing spans, e.g.
+// `block{height=840000}:upstream{url=...}`; JSON output lists them under "spans", so one block's
+// ingestion can be followed by filtering on its height.
+pub fn init(config: &LogConfig) {
+    let subscriber = tracing_subscriber::fmt().with_env_filter(EnvFilter::new(&config.filter));
+    match config.format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().with_current_span(true).with_span_list(true).init(),
+    }
+}
@@ -1,19 +1,42 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use diesel::prelude::*;
 use diesel::r2d2::{self, ConnectionManager};// connection pool
 use dotenv::dotenv;//.env
 use serde::{Deserialize, Serialize};
 use std::env;
+use std::error::Error;
 use std::sync::Arc;
 use tokio::time::{self, Duration};
 use tokio::sync::Mutex;//async lock
 use warp::Filter;// http route
 use chrono::{NaiveDateTime, TimeZone, Utc};
+use tracing::{debug, error, info, warn};
 use diesel::pg::Pg;
 
 
+mod address;
+mod block_price;
+mod config;
+mod error;
+mod fees;
+mod health;
+mod http;
+mod logging;
+mod mempool;
+mod metrics;
+mod price;
 mod schema;
-use schema::{offchain_data, block_info, transactions, transaction_inputs, transaction_outputs};
+mod source;
+mod supervisor;
+#[cfg(test)]
+mod test_util;
+mod utxo;
+use schema::{offchain_data, block_info, transactions, transaction_inputs, transaction_outputs, backfill_progress};
+use config::{Config, SourceConfig};
+use error::{reject, with_connection, ApiError, CONNECTION_TIMEOUT};
+use price::PriceProvider;
+use supervisor::{Shutdown, WorkerResult};
+use source::{ApiBlockInfo, ApiTransaction, ApiTransactionInput, BitcoindSource, BlkFileSource, BlockSource, EsploraSource, MempoolSource};
 
 
 
@@ -30,22 +53,36 @@ pub struct OffchainData {
     pub high: Option<f64>,
     pub low: Option<f64>,
     pub timestamp: NaiveDateTime,
+    pub currency: String,
 }
 
 
 
 
-async fn insert_or_update_offchain_data(pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>, data: OffchainData) -> Result<(), diesel::result::Error> {
-    let mut conn = pool.get().expect("Failed to get connection from pool");
+async fn insert_or_update_offchain_data(pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>, data: OffchainData) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut conn = pool.get()?;
 
-    // Check if data already exists
+    // One row per block height and currency: later polls at the same tip refresh it instead of
+    // adding rows.
     let existing_data = offchain_data::table
         .filter(offchain_data::block_height.eq(data.block_height))
-        .filter(offchain_data::btc_price.eq(data.btc_price))
+        .filter(offchain_data::currency.eq(&data.currency))
         .first::<OffchainData>(&mut conn)
         .optional()?;
 
-    if existing_data.is_none() {
+    if let Some(existing_data) = existing_data {
+        diesel::update(offchain_data::table.find(existing_data.id))
+            .set((
+                offchain_data::btc_price.eq(data.btc_price),
+                offchain_data::market_sentiment.eq(data.market_sentiment),
+                offchain_data::volume.eq(data.volume),
+                offchain_data::high.eq(data.high),
+                offchain_data::low.eq(data.low),
+                offchain_data::timestamp.eq(data.timestamp),
+            ))
+            .execute(&mut conn)?;
+        Ok(())
+    } else {
         // Insert new data without specifying the ID
         let new_data = (
             offchain_data::block_height.eq(data.block_height),
@@ -55,94 +92,124 @@ async fn insert_or_update_offchain_data(pool: Arc<r2d2::Pool<ConnectionManager<P
             offchain_data::high.eq(data.high),
             offchain_data::low.eq(data.low),
             offchain_data::timestamp.eq(data.timestamp),
+            offchain_data::currency.eq(&data.currency),
         );
 
         match diesel::insert_into(offchain_data::table)
             .values(&new_data)
             .execute(&mut conn) {
             Ok(_) => {
-                println!("Data inserted successfully");
+                debug!("Offchain data inserted");
                 Ok(())
             },
             Err(e) => {
-                eprintln!("Error inserting data: {}", e);
-                Err(e)
+                error!(error = %e, "Error inserting offchain data");
+                Err(e.into())
             }
         }
-    } else {
-        diesel::update(offchain_data::table.find(existing_data.unwrap().id))
-            .set((
-                offchain_data::market_sentiment.eq(data.market_sentiment),
-                offchain_data::volume.eq(data.volume),
-                offchain_data::high.eq(data.high),
-                offchain_data::low.eq(data.low),
-                offchain_data::timestamp.eq(data.timestamp),
-            ))
-            .execute(&mut conn)?;
-        Ok(())
     }
 }
 
 
 
+// Fetches and stores a price in every tracked currency against the current tip height, once per
+// poll interval.
+async fn fetch_offchain_data(
+    pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>,
+    source: Arc<dyn BlockSource>,
+    providers: Vec<Arc<dyn PriceProvider>>,
+    currencies: Vec<String>,
+    max_deviation: f64,
+    poll_interval: Duration,
+    mut shutdown: Shutdown,
+) -> WorkerResult {
+    let mut interval = time::interval(poll_interval);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.wait() => break,
+        }
 
-async fn fetch_and_store_offchain_data(pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>, block_height: i32) {
-    let client = reqwest::Client::new();
-    println!("Fetching offchain data for block height: {}", block_height);
-    let response = client.get("https://api.coingecko.com/api/v3/coins/bitcoin/market_chart")
-        .query(&[("vs_currency", "usd"), ("days", "1"), ("interval", "daily")])
-        .send().await;
-
-    if let Ok(res) = response {
-        if res.status().is_success() {
-            if let Ok(offchain_data) = res.json::<serde_json::Value>().await {
-                if let Some(prices) = offchain_data["prices"].as_array() {
-                    if let Some(price) = prices.first() {
-                        let btc_price = price[1].as_f64().unwrap_or(0.0);
-                        let market_sentiment = offchain_data["market_caps"].as_array()
-                            .and_then(|caps| caps.first())
-                            .and_then(|cap| cap[1].as_f64());
-                        let volume = offchain_data["total_volumes"].as_array()
-                            .and_then(|volumes| volumes.first())
-                            .and_then(|volume| volume[1].as_f64());
-                        let high = offchain_data["prices"].as_array()
-                            .and_then(|prices| prices.iter().map(|price| price[1].as_f64().unwrap_or(0.0)).max_by(|a, b| a.partial_cmp(b).unwrap()));
-                        let low = offchain_data["prices"].as_array()
-                            .and_then(|prices| prices.iter().map(|price| price[1].as_f64().unwrap_or(0.0)).min_by(|a, b| a.partial_cmp(b).unwrap()));
-                        let timestamp = Utc::now().naive_utc();
-
-                        let new_data = OffchainData {
-                            id: 0,
-                            block_height,
-                            btc_price,
-                            market_sentiment,
-                            volume,
-                            high,
-                            low,
-                            timestamp,
-                        };
-
-                        match insert_or_update_offchain_data(pool.clone(), new_data).await {
-                            Ok(_) => println!("Offchain data processed successfully."),
-                            Err(e) => eprintln!("Error processing offchain data: {}", e),
-                        }
-                    }
+        // get the real height
+        match source.tip_height().await {
+            Ok(block_height) => {
+                debug!(block_height, "Fetched tip height for offchain data");
+                for currency in &currencies {
+                    fetch_and_store_offchain_data(pool.clone(), &providers, currency, max_deviation, block_height).await;
                 }
             }
+            Err(e) => error!(error = %e, "Error fetching tip height for offchain data"),
         }
     }
+    Ok(())
+}
+
+#[tracing::instrument(name = "offchain", skip_all, fields(block_height = block_height, currency = %currency))]
+async fn fetch_and_store_offchain_data(
+    pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>,
+    providers: &[Arc<dyn PriceProvider>],
+    currency: &str,
+    max_deviation: f64,
+    block_height: i32,
+) {
+    debug!("Fetching offchain data");
+    let quotes = price::fetch_quotes(providers, currency).await;
+    let accepted = price::accepted_quotes(&quotes, max_deviation);
+    for ((name, quote), accepted) in quotes.iter().zip(&accepted) {
+        if !accepted {
+            metrics::PRICE_OUTLIERS.with_label_values(&[name]).inc();
+            warn!(provider = name, price = quote.price, "Rejecting price quote as an outlier");
+        }
+    }
+
+    match pool.get() {
+        Ok(mut conn) => {
+            if let Err(e) = price::store_quotes(&mut conn, block_height, currency, &quotes, &accepted) {
+                error!(error = %e, "Error storing price quotes");
+            }
+        }
+        Err(e) => error!(error = %e, "Error getting connection for price quotes"),
+    }
+
+    let Some(aggregated) = price::aggregate(&quotes, &accepted) else {
+        warn!("No accepted price quotes available");
+        return;
+    };
+
+    let new_data = OffchainData {
+        id: 0,
+        block_height,
+        btc_price: aggregated.price,
+        market_sentiment: aggregated.market_cap,
+        volume: aggregated.volume,
+        high: aggregated.high,
+        low: aggregated.low,
+        timestamp: Utc::now().naive_utc(),
+        currency: currency.to_string(),
+    };
+
+    match insert_or_update_offchain_data(pool.clone(), new_data).await {
+        Ok(_) => info!(price = aggregated.price, "Offchain data processed"),
+        Err(e) => error!(error = %e, "Error processing offchain data"),
+    }
 }
 
 
 
 async fn handle_get_offchain_data(
     pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>,
+    query: CurrencyQuery,
+    currencies: Arc<Vec<String>>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let mut conn = pool.get().expect("Failed to get connection from pool");
-    let results: Vec<OffchainData> = offchain_data::table
-        .order(offchain_data::id.desc())
-        .load::<OffchainData>(&mut conn)
-        .expect("Error loading offchain data");
+    let currency = query.currency(&currencies).map_err(reject)?;
+    let results: Vec<OffchainData> = with_connection(pool, CONNECTION_TIMEOUT, move |conn| {
+        Ok(offchain_data::table
+            .filter(offchain_data::currency.eq(currency))
+            .order(offchain_data::id.desc())
+            .load::<OffchainData>(conn)?)
+    })
+    .await
+    .map_err(reject)?;
 
     Ok(warp::reply::json(&results))
 }
@@ -151,7 +218,7 @@ async fn handle_get_offchain_data(
 //==============================
 
 
-#[derive(Queryable, Identifiable, Insertable, Debug, AsChangeset, Serialize)]
+#[derive(Queryable, Identifiable, Debug, AsChangeset, Serialize)]
 #[diesel(table_name = block_info)]
 pub struct BlockInfo {
     pub id: i32,
@@ -162,180 +229,394 @@ pub struct BlockInfo {
     pub timestamp: NaiveDateTime,
     pub size: i32,
     pub weight: i32,
+    pub hash: Option<String>,
+    pub previous_hash: Option<String>,
+    pub complete: bool,
 }
 
-#[derive(Queryable, Identifiable, Insertable, Debug, AsChangeset, Serialize)]
+#[derive(Queryable, Identifiable, Debug, AsChangeset, Serialize)]
 #[diesel(table_name = transactions)]
 pub struct Transaction {
     pub id: i32,
     pub block_height: i32,
     pub hash: String,
     pub btc: f64,
-    pub fee: i64,
+    // None while the outputs the transaction spends are not known.
+    pub fee: Option<i64>,
     pub time: i64,
+    pub vsize: i32,
+    pub weight: i32,
 }
 
-#[derive(Queryable, Identifiable, Insertable, Debug, AsChangeset, Serialize)]
+#[derive(Queryable, Identifiable, Debug, AsChangeset, Serialize)]
 #[diesel(table_name = transaction_inputs)]
 pub struct TransactionInput {
     pub id: i32,
     pub transaction_id: i32,
     pub previous_output: String,
-    pub value: i64,
+    pub value: Option<i64>,
+    pub vin: i32,
+    pub previous_vout: Option<i32>,
+    pub sequence: i64,
+    pub scriptsig: String,
+    pub witness: Vec<String>,
+    pub is_coinbase: bool,
 }
 
-#[derive(Queryable, Identifiable, Insertable, Debug, AsChangeset, Serialize)]
+#[derive(Queryable, Identifiable, Debug, AsChangeset, Serialize)]
 #[diesel(table_name = transaction_outputs)]
 pub struct TransactionOutput {
     pub id: i32,
     pub transaction_id: i32,
     pub address: String,
     pub value: i64,
+    pub vout: i32,
+    pub script_hex: String,
+    pub script_asm: String,
+    pub script_type: String,
 }
 
-
-#[derive(Deserialize)]
-struct ApiBlockInfo {
-    id: String,
-    height: i32,
-    version: i32,
-    timestamp: i64,
-    tx_count: i32,
-    size: i32,
-    weight: i32,
-    merkle_root: String,
-    previousblockhash: String,
-    mediantime: i64,
-    nonce: i64,
-    bits: i32,
-    difficulty: f64,
-    tx: Option<Vec<ApiTransaction>>,
+// Insertable counterparts of the models above; ids come from the SERIAL columns.
+#[derive(Insertable)]
+#[diesel(table_name = block_info)]
+pub struct NewBlockInfo {
+    pub height: i32,
+    pub avg_tx_count: i32,
+    pub difficulty: f64,
+    pub block_time: i32,
+    pub timestamp: NaiveDateTime,
+    pub size: i32,
+    pub weight: i32,
+    pub hash: Option<String>,
+    pub previous_hash: Option<String>,
+    pub complete: bool,
 }
 
-#[derive(Deserialize)]
-struct ApiTransaction {
-    txid: String,
-    fee: i64,
-    vin: Vec<ApiTransactionInput>,
-    vout: Vec<ApiTransactionOutput>,
+#[derive(Insertable)]
+#[diesel(table_name = transactions)]
+pub struct NewTransaction {
+    pub block_height: i32,
+    pub hash: String,
+    pub btc: f64,
+    pub fee: Option<i64>,
+    pub time: i64,
+    pub vsize: i32,
+    pub weight: i32,
 }
 
-#[derive(Deserialize)]
-struct ApiTransactionInput {
-    txid: String,
-    vout: u32,
-    sequence: i64,
-    value: Option<i64>,
-    prevout: Option<PrevOut>,
+#[derive(Insertable)]
+#[diesel(table_name = transaction_inputs)]
+pub struct NewTransactionInput {
+    pub transaction_id: i32,
+    pub previous_output: String,
+    pub value: Option<i64>,
+    pub vin: i32,
+    pub previous_vout: Option<i32>,
+    pub sequence: i64,
+    pub scriptsig: String,
+    pub witness: Vec<String>,
+    pub is_coinbase: bool,
+}
 
+#[derive(Insertable)]
+#[diesel(table_name = transaction_outputs)]
+pub struct NewTransactionOutput {
+    pub transaction_id: i32,
+    pub address: String,
+    pub value: i64,
+    pub vout: i32,
+    pub script_hex: String,
+    pub script_asm: String,
+    pub script_type: String,
 }
 
 
-#[derive(Deserialize)]
-struct PrevOut {
-    scriptpubkey: String,
-    scriptpubkey_asm: String,
-    scriptpubkey_type: String,
-    scriptpubkey_address: String,
-    value: i64,
+#[derive(Serialize)]
+struct BlockDetailData {
+    block_info: BlockInfo,
+    currency: String,
+    // Price at the block's timestamp in `currency`, once it has been looked up.
+    btc_price: Option<f64>,
+    transactions: Vec<FiatTransaction>,
+    inputs: Vec<TransactionInput>,
+    outputs: Vec<TransactionOutput>,
 }
 
-
-#[derive(Deserialize, Debug)]
-struct ApiTransactionOutput {
-    value: f64,
-    n: Option<u32>,
-    script_pub_key: Option<ApiScriptPubKey>,
+// A transaction with its output total and fee also given in fiat at the block's price.
+#[derive(Serialize)]
+struct FiatTransaction {
+    #[serde(flatten)]
+    transaction: Transaction,
+    fiat_value: Option<f64>,
+    fiat_fee: Option<f64>,
 }
 
+// ?page=&per_page= on paginated endpoints. Pages start at 1.
+#[derive(Deserialize)]
+pub struct PageQuery {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
 
+impl PageQuery {
+    pub fn page(&self) -> i64 {
+        self.page.unwrap_or(1).max(1)
+    }
 
-#[derive(Deserialize, Debug)]
-struct ApiScriptPubKey {
-    hex: String,
-    asm: String,
-    addresses: Vec<String>,
+    pub fn per_page(&self) -> i64 {
+        self.per_page.unwrap_or(25).clamp(1, 100)
+    }
 }
 
-
+// ?currency= on endpoints that report fiat values; the first configured currency when absent.
 #[derive(Deserialize)]
-struct BlockHashResponse {
-    id: String,
+pub struct CurrencyQuery {
+    pub currency: Option<String>,
 }
 
-#[derive(Serialize)]
-struct BlockDetailData {
-    block_info: BlockInfo,
-    transactions: Vec<Transaction>,
-    inputs: Vec<TransactionInput>,
-    outputs: Vec<TransactionOutput>,
+impl CurrencyQuery {
+    // Only currencies listed in offchain.currencies have prices, so anything else is refused.
+    pub fn currency(&self, tracked: &[String]) -> Result<String, ApiError> {
+        let Some(requested) = &self.currency else {
+            return tracked.first().cloned().ok_or_else(|| ApiError::Internal("No currencies configured".to_string()));
+        };
+        let currency = requested.trim().to_lowercase();
+        if tracked.contains(&currency) {
+            Ok(currency)
+        } else {
+            Err(ApiError::BadRequest(format!("Unsupported currency {}, expected one of {}", requested, tracked.join(", "))))
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    println!("Starting the application...");
-
     dotenv().ok();
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let manager = ConnectionManager::<PgConnection>::new(&database_url);
-
-    println!("Creating connection pool...");
-    let pool = Arc::new(r2d2::Pool::builder().build(manager).expect("Failed to create pool"));
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        print!("{}", config::usage());
+        return;
+    }
+    let config = match Config::load(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration:\n{}", e);
+            std::process::exit(2);
+        }
+    };
+    logging::init(&config.log);
+    for warning in &config.warnings {
+        warn!("{}", warning);
+    }
+    metrics::register();
+    info!("Starting the application...");
+
+    let manager = ConnectionManager::<PgConnection>::new(&config.database.url);
+
+    debug!("Creating connection pool...");
+    let pool = Arc::new(
+        r2d2::Pool::builder()
+            .max_size(config.database.pool_size)
+            .build(manager)
+            .expect("Failed to create pool"),
+    );
+
+    debug!("Creating synchronization mechanism...");
+    let is_fetching = Arc::new(Mutex::new(()));
+
+    let http = Arc::new(http::HttpClient::new(&config.http));
+
+    debug!("Creating block source...");
+    let (source, mempool_source) = match sources(&config, &http) {
+        Ok(sources) => sources,
+        Err(e) => {
+            error!(error = %e, "Failed to set up the block source");
+            std::process::exit(1);
+        }
+    };
+    let price_history = match price::history(&config.offchain, &http) {
+        Ok(history) => history,
+        Err(e) => {
+            error!(error = %e, "Failed to set up the price history");
+            std::process::exit(1);
+        }
+    };
 
-    println!("Creating synchronization mechanism...");
-    let is_fetching = Arc::new(Mutex::new(false));
+    debug!("Spawning tasks...");
+    let shutdown_timeout = config.supervisor.shutdown_timeout;
+    let supervisor = Arc::new(supervisor::Supervisor::new(config.supervisor));
 
-    println!("Spawning tasks...");
     let pool_clone_for_block_info = Arc::clone(&pool);
+    let source_clone = Arc::clone(&source);
     let is_fetching_clone = Arc::clone(&is_fetching);
-    tokio::spawn(async move {
-        fetch_and_store_block_info(pool_clone_for_block_info, "https://blockstream.info/api/blocks/tip/height".to_string(), is_fetching_clone).await;
+    let tip_interval = config.intervals.tip;
+    supervisor.spawn("tip", move |shutdown| {
+        fetch_and_store_block_info(pool_clone_for_block_info.clone(), source_clone.clone(), is_fetching_clone.clone(), tip_interval, shutdown)
+    });
+
+    if let Some((start_height, end_height)) = config.backfill {
+        let pool_clone_for_backfill = Arc::clone(&pool);
+        let source_clone = Arc::clone(&source);
+        let is_fetching_clone = Arc::clone(&is_fetching);
+        let retry_delay = config.intervals.retry;
+        supervisor.spawn("backfill", move |shutdown| {
+            backfill_block_info(pool_clone_for_backfill.clone(), source_clone.clone(), is_fetching_clone.clone(), start_height, end_height, retry_delay, shutdown)
+        });
+    }
+
+    if let Some(mempool_source) = mempool_source {
+        let pool_clone_for_mempool = Arc::clone(&pool);
+        let mempool_interval = config.intervals.mempool;
+        supervisor.spawn("mempool", move |shutdown| {
+            mempool::fetch_and_store_mempool(pool_clone_for_mempool.clone(), mempool_source.clone(), mempool_interval, shutdown)
+        });
+    }
+
+    let pool_clone_for_block_prices = Arc::clone(&pool);
+    let currencies = config.offchain.currencies.clone();
+    let block_prices_interval = config.intervals.block_prices;
+    supervisor.spawn("block_prices", move |shutdown| {
+        block_price::fetch_and_store_block_prices(pool_clone_for_block_prices.clone(), price_history.clone(), currencies.clone(), block_prices_interval, shutdown)
     });
 
     let pool_clone_for_offchain = Arc::clone(&pool);
-    tokio::spawn(async move {
-        let mut interval = time::interval(Duration::from_secs(10)); // every 10s run
-        loop {
-            interval.tick().await;
-
-            println!("Fetching offchain data...");
-            // get the real height
-            let height_url = "https://blockstream.info/api/blocks/tip/height";
-            let client = reqwest::Client::new();
-            if let Ok(response) = client.get(height_url).send().await {
-                if let Ok(height_text) = response.text().await {
-                    if let Ok(block_height) = height_text.trim().parse::<i32>() {
-                        println!("Fetched block height: {}", block_height);
-                        fetch_and_store_offchain_data(pool_clone_for_offchain.clone(), block_height).await;
-                    }
-                }
-            }
-        }
+    let source_clone = Arc::clone(&source);
+    let price_providers = price::providers(&config.offchain, &http);
+    let currencies = config.offchain.currencies.clone();
+    // Quotes further than this fraction from the median price are left out of the aggregate.
+    let max_price_deviation = config.offchain.max_deviation;
+    let offchain_interval = config.intervals.offchain;
+    supervisor.spawn("offchain", move |shutdown| {
+        fetch_offchain_data(
+            pool_clone_for_offchain.clone(),
+            source_clone.clone(),
+            price_providers.clone(),
+            currencies.clone(),
+            max_price_deviation,
+            offchain_interval,
+            shutdown,
+        )
     });
 
-    println!("Setting up routes...");
+    debug!("Setting up routes...");
+    let detail_currencies = Arc::new(config.offchain.currencies.clone());
+    let offchain_currencies = Arc::clone(&detail_currencies);
     let block_info_route = warp::path("block-info")
         .and(warp::get())
         .and(with_db(Arc::clone(&pool)))
-        .and_then(handle_get_block_info)
-        .with(warp::cors().allow_any_origin());
+        .and(warp::query::<PageQuery>())
+        .and_then(handle_get_block_info);
 
     let block_detail_route = warp::path!("block" / i32)
         .and(warp::get())
+        .and(warp::query::<CurrencyQuery>())
         .and(with_db(Arc::clone(&pool)))
-        .and_then(|height, pool| handle_get_block_detail(pool, height))
-        .with(warp::cors().allow_any_origin());
+        .and_then(move |height, query, pool| handle_get_block_detail(pool, height, query, Arc::clone(&detail_currencies)));
 
     let offchain_data_route = warp::path("offchain-data")
         .and(warp::get())
+        .and(warp::query::<CurrencyQuery>())
         .and(with_db(pool.clone()))
-        .and_then(handle_get_offchain_data)
-        .with(warp::cors().allow_any_origin());
+        .and_then(move |query, pool| handle_get_offchain_data(pool, query, Arc::clone(&offchain_currencies)));
 
-    println!("Starting server...");
-    warp::serve(block_info_route.or(block_detail_route).or(offchain_data_route))
-        .run(([0, 0, 0, 0], 8000))
-        .await;
+    let utxo_set_route = warp::path("utxo-set")
+        .and(warp::get())
+        .and(with_db(pool.clone()))
+        .and_then(utxo::handle_get_utxo_set);
+
+    let address_route = warp::path!("address" / String)
+        .and(warp::get())
+        .and(warp::query::<PageQuery>())
+        .and(with_db(pool.clone()))
+        .and_then(|address, query, pool| address::handle_get_address(pool, address, query));
+
+    let mempool_route = warp::path!("mempool")
+        .and(warp::get())
+        .and(with_db(pool.clone()))
+        .and_then(mempool::handle_get_mempool);
+
+    let mempool_txs_route = warp::path!("mempool" / "txs")
+        .and(warp::get())
+        .and(warp::query::<PageQuery>())
+        .and(with_db(pool.clone()))
+        .and_then(|query, pool| mempool::handle_get_mempool_txs(pool, query));
+
+    let fee_estimate_blocks = config.fees.estimate_blocks;
+    let recommended_fees_route = warp::path!("fees" / "recommended")
+        .and(warp::get())
+        .and(with_db(pool.clone()))
+        .and_then(move |pool| fees::handle_get_recommended_fees(pool, fee_estimate_blocks));
+
+    let metrics_route = warp::path!("metrics")
+        .and(warp::get())
+        .and(with_db(pool.clone()))
+        .and_then(metrics::handle_get_metrics);
+
+    let health_supervisor = Arc::clone(&supervisor);
+    let health_route = warp::path!("health")
+        .and(warp::get())
+        .and(with_db(pool.clone()))
+        .and_then(move |pool| health::handle_get_health(pool, Arc::clone(&health_supervisor)));
+
+    let health_config = Arc::new(config.health);
+    let ready_supervisor = Arc::clone(&supervisor);
+    let ready_route = warp::path!("ready")
+        .and(warp::get())
+        .and(with_db(pool.clone()))
+        .and_then(move |pool| health::handle_get_ready(pool, Arc::clone(&health_config), Arc::clone(&ready_supervisor)));
+
+    info!(bind = %config.api.bind, "Starting server...");
+    let mut server_shutdown = supervisor.shutdown_handle();
+    // The API routes come last and turn every rejection, unknown paths included, into a JSON
+    // error inside the CORS wrapper, so browsers can read error bodies too. The outer recover only
+    // sees what CORS itself rejects.
+    let api_routes = block_info_route
+        .or(block_detail_route)
+        .or(offchain_data_route)
+        .or(utxo_set_route)
+        .or(address_route)
+        .or(mempool_route)
+        .or(mempool_txs_route)
+        .or(recommended_fees_route)
+        .recover(error::handle_rejection)
+        .with(cors(&config.api.cors_origins));
+    let (_, server) = warp::serve(
+        metrics_route
+            .or(health_route)
+            .or(ready_route)
+            .or(api_routes)
+            .recover(error::handle_rejection)
+            .with(metrics::track())
+            .with(warp::trace::request()),
+    )
+        .bind_with_graceful_shutdown(config.api.bind, async move { server_shutdown.wait().await });
+    let server = tokio::spawn(server);
+
+    supervisor::signal().await;
+    info!(timeout = ?shutdown_timeout, "Shutting down...");
+    supervisor.shutdown();
+    // The server stops accepting connections and drains the open ones while the workers finish
+    // whatever block or price write they are in the middle of.
+    let drained = time::timeout(shutdown_timeout, async {
+        if let Err(e) = server.await {
+            error!(error = %e, "Server task failed");
+        }
+        supervisor.join().await;
+    })
+    .await;
+    match drained {
+        Ok(()) => info!("Shutdown complete"),
+        Err(_) => warn!("Shutdown timed out, exiting with work still in flight"),
+    }
+}
+
+// "*" allows any origin, as before; otherwise only the listed ones.
+fn cors(origins: &[String]) -> warp::cors::Builder {
+    if origins.iter().any(|origin| origin == "*") {
+        warp::cors().allow_any_origin()
+    } else {
+        warp::cors().allow_origins(origins.iter().map(String::as_str))
+    }
 }
 
 fn with_db(
@@ -344,276 +625,641 @@ fn with_db(
     warp::any().map(move || pool.clone())
 }
 
+// Highest blocks first, a page at a time. Ids follow insertion order, which a backfill breaks, so
+// blocks are ordered by height.
 async fn handle_get_block_info(
     pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>,
+    query: PageQuery,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    println!("Handling get block info...");
-    let mut conn = pool.get().expect("Failed to get connection from pool");
-    let results: Vec<BlockInfo> = block_info::table
-        .order(block_info::id.desc())
-        .load::<BlockInfo>(&mut conn)
-        .expect("Error loading block info");
-
-    println!("Returning block info...");
+    debug!("Handling get block info...");
+    let page = query.page();
+    let per_page = query.per_page();
+    let results: Vec<BlockInfo> = with_connection(pool, CONNECTION_TIMEOUT, move |conn| {
+        Ok(block_info::table
+            .order(block_info::height.desc())
+            .limit(per_page)
+            .offset((page - 1) * per_page)
+            .load::<BlockInfo>(conn)?)
+    })
+    .await
+    .map_err(reject)?;
+
     Ok(warp::reply::json(&results))
 }
 
 async fn handle_get_block_detail(
     pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>,
     height: i32,
+    query: CurrencyQuery,
+    currencies: Arc<Vec<String>>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    println!("Handling get block detail for height: {}", height);
-    let mut conn = pool.get().expect("Failed to get connection from pool");
-
-    let block_info_result: Option<BlockInfo> = block_info::table
-        .filter(block_info::height.eq(height))
-        .first(&mut conn)
-        .optional()
-        .expect("Error loading block info");
-
-    if let Some(block_info) = block_info_result {
-        println!("Block info found for height: {}", height);
-        let transactions_result: Vec<Transaction> = transactions::table
-            .filter(transactions::block_height.eq(height))
-            .load::<Transaction>(&mut conn)
-            .expect("Error loading transactions");
-
-        let inputs_result: Vec<TransactionInput> = transaction_inputs::table
-            .filter(transaction_inputs::transaction_id.eq_any(transactions_result.iter().map(|tx| tx.id)))
-            .load::<TransactionInput>(&mut conn)
-            .expect("Error loading transaction inputs");
-
-        let outputs_result: Vec<TransactionOutput> = transaction_outputs::table
-            .filter(transaction_outputs::transaction_id.eq_any(transactions_result.iter().map(|tx| tx.id)))
-            .load::<TransactionOutput>(&mut conn)
-            .expect("Error loading transaction outputs");
-
-        let block_detail = BlockDetailData {
-            block_info,
-            transactions: transactions_result,
-            inputs: inputs_result,
-            outputs: outputs_result,
-        };
-
-        println!("Returning block detail for height: {}", height);
-        Ok(warp::reply::json(&block_detail))
-    } else {
-        println!("Block not found for height: {}", height);
-        let not_found = warp::reply::json(&"Block not found");
-        Ok(not_found)
+    debug!(height, "Handling get block detail...");
+    if height < 0 {
+        return Err(reject(ApiError::BadRequest(format!("Invalid block height {}", height))));
     }
+    let currency = query.currency(&currencies).map_err(reject)?;
+    let priced_in = currency.clone();
+    let (block_info, transactions_result, inputs_result, outputs_result, btc_price) =
+        with_connection(pool, CONNECTION_TIMEOUT, move |conn| {
+            let block_info: BlockInfo = block_info::table
+                .filter(block_info::height.eq(height))
+                .first(conn)
+                .optional()?
+                .ok_or_else(|| ApiError::NotFound(format!("Block {} not found", height)))?;
+
+            let transactions_result: Vec<Transaction> = transactions::table
+                .filter(transactions::block_height.eq(height))
+                .load::<Transaction>(conn)?;
+
+            let inputs_result: Vec<TransactionInput> = transaction_inputs::table
+                .filter(transaction_inputs::transaction_id.eq_any(transactions_result.iter().map(|tx| tx.id)))
+                .load::<TransactionInput>(conn)?;
+
+            let outputs_result: Vec<TransactionOutput> = transaction_outputs::table
+                .filter(transaction_outputs::transaction_id.eq_any(transactions_result.iter().map(|tx| tx.id)))
+                .load::<TransactionOutput>(conn)?;
+
+            let btc_price = block_price::price_for_block(conn, height, &priced_in)?;
+
+            Ok((block_info, transactions_result, inputs_result, outputs_result, btc_price))
+        })
+        .await
+        .map_err(reject)?;
+
+    let transactions = transactions_result.into_iter().map(|transaction| FiatTransaction {
+        fiat_value: btc_price.map(|price| transaction.btc * price),
+        fiat_fee: btc_price.zip(transaction.fee).map(|(price, fee)| fee as f64 / 100_000_000.0 * price),
+        transaction,
+    }).collect();
+
+    let block_detail = BlockDetailData {
+        block_info,
+        currency,
+        btc_price,
+        transactions,
+        inputs: inputs_result,
+        outputs: outputs_result,
+    };
+
+    Ok(warp::reply::json(&block_detail))
 }
 async fn fetch_and_store_block_info(
     pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>,
-    height_url: String,
-    is_fetching: Arc<Mutex<bool>>,
-) {
-    let client = reqwest::Client::new();
-    let mut interval = time::interval(Duration::from_secs(10));
+    source: Arc<dyn BlockSource>,
+    is_fetching: Arc<Mutex<()>>,
+    poll_interval: Duration,
+    mut shutdown: Shutdown,
+) -> WorkerResult {
+    let mut interval = time::interval(poll_interval);
 
     loop {
-        interval.tick().await;
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.wait() => break,
+        }
+
+        // Held for the whole iteration so the tip and backfill workers never ingest at the same
+        // time. A panic releases it while unwinding, so a restarted worker is not locked out.
+        let _is_fetching_guard = is_fetching.lock().await;
 
-        let mut is_fetching_guard = is_fetching.lock().await;
-        if *is_fetching_guard {
-            continue;
+        let tip = match source.tip_height().await {
+            Ok(tip) => tip,
+            Err(e) => {
+                error!(error = %e, "Error fetching tip height");
+                continue;
+            }
+        };
+        metrics::UPSTREAM_TIP_HEIGHT.set(tip as i64);
+        let stored = match highest_stored_height(&pool) {
+            Ok(stored) => stored,
+            Err(e) => {
+                error!(error = %e, "Error loading the highest stored block");
+                continue;
+            }
+        };
+
+        // A failed block is retried from the same height on the next tick.
+        for height in catch_up_heights(stored, tip) {
+            if shutdown.is_triggered() {
+                break;
+            }
+            if let Err(e) = ingest_block_at_height(source.as_ref(), pool.clone(), height).await {
+                error!(height, error = %e, "Error ingesting block");
+                break;
+            }
         }
-        *is_fetching_guard = true;
-
-        let response = client.get(&height_url).send().await;
-        if let Ok(res) = response {
-            if res.status().is_success() {
-                if let Ok(height_text) = res.text().await {
-                    if let Ok(height) = height_text.trim().parse::<i32>() {
-                        let hash_url = format!("https://blockstream.info/api/block-height/{}", height);
-                        if let Ok(hash_res) = client.get(&hash_url).send().await {
-                            if hash_res.status().is_success() {
-                                if let Ok(hash_text) = hash_res.text().await {
-                                    let hash_info = BlockHashResponse { id: hash_text.trim().to_string() };
-
-                                    let block_url = format!("https://blockstream.info/api/block/{}", hash_info.id);
-                                    if let Ok(block_res) = client.get(&block_url).send().await {
-                                        if block_res.status().is_success() {
-                                            if let Ok(block_text) = block_res.text().await {
-                                                if let Ok(api_block_info) = serde_json::from_str::<ApiBlockInfo>(&block_text) {
-                                                    let mut conn = pool.get().expect("Failed to get connection from pool");
-
-                                                    let existing_block: Option<BlockInfo> = block_info::table
-                                                        .filter(block_info::height.eq(api_block_info.height))
-                                                        .first(&mut conn)
-                                                        .optional()
-                                                        .expect("Error querying block info");
-
-                                                    if existing_block.is_none() {
-                                                        let latest_info: Option<BlockInfo> = block_info::table
-                                                            .order(block_info::id.desc())
-                                                            .first(&mut conn)
-                                                            .optional()
-                                                            .expect("Error querying latest block info");
-
-                                                        let new_id = latest_info.as_ref().map_or(1, |latest| latest.id + 1);
-
-                                                        let timestamp = Utc.timestamp_opt(api_block_info.timestamp, 0).unwrap();
-
-                                                        let new_info = BlockInfo {
-                                                            id: new_id,
-                                                            height: api_block_info.height,
-                                                            avg_tx_count: api_block_info.tx_count,
-                                                            difficulty: api_block_info.difficulty,
-                                                            block_time: api_block_info.mediantime as i32,
-                                                            timestamp: timestamp.naive_utc(),
-                                                            size: api_block_info.size,
-                                                            weight: api_block_info.weight,
-                                                        };
-
-                                                        diesel::insert_into(block_info::table)
-                                                            .values(&new_info)
-                                                            .execute(&mut conn)
-                                                            .expect("Error inserting block info");
-                                                    }
-
-                                                    let txs_url = format!("https://blockstream.info/api/block/{}/txs", hash_info.id);
-                                                    if let Ok(txs_res) = client.get(&txs_url).send().await {
-                                                        if txs_res.status().is_success() {
-                                                            if let Ok(txs_text) = txs_res.text().await {
-                                                                if let Ok(txs) = serde_json::from_str::<Vec<ApiTransaction>>(&txs_text) {
-                                                                    for mut tx in txs {
-                                                                        let latest_tx: Option<Transaction> = transactions::table
-                                                                            .order(transactions::id.desc())
-                                                                            .first(&mut conn)
-                                                                            .optional()
-                                                                            .expect("Error querying latest transaction");
-
-                                                                        let new_tx_id = latest_tx.as_ref().map_or(1, |latest| latest.id + 1);
-
-                                                                        let new_tx = Transaction {
-                                                                            id: new_tx_id,
-                                                                            block_height: api_block_info.height,
-                                                                            hash: tx.txid.clone(),
-                                                                            btc: tx.vout.iter().map(|vout| vout.value).sum(),
-                                                                            fee: tx.fee,
-                                                                            time: api_block_info.timestamp,
-                                                                        };
-
-                                                                        diesel::insert_into(transactions::table)
-                                                                            .values(&new_tx)
-                                                                            .execute(&mut conn)
-                                                                            .expect("Error inserting transaction");
-
-
-                                                                        for vin in tx.vin {
-                                                                            let latest_input: Option<TransactionInput> = transaction_inputs::table
-                                                                                .order(transaction_inputs::id.desc())
-                                                                                .first(&mut conn)
-                                                                                .optional()
-                                                                                .expect("Error querying latest transaction input");
-
-                                                                            let new_input_id = latest_input.as_ref().map_or(1, |latest| latest.id + 1);
-
-                                                                            let value = vin.prevout.as_ref().map_or(0, |prevout| prevout.value);
-
-
-
-                                                                            let new_input = TransactionInput {
-                                                                                id: new_input_id,
-                                                                                transaction_id: new_tx_id,
-                                                                                previous_output: vin.txid.clone(),
-                                                                                value,
-                                                                            };
-
-                                                                            match diesel::insert_into(transaction_inputs::table)
-                                                                                .values(&new_input)
-                                                                                .execute(&mut conn)
-                                                                            {
-                                                                                Ok(_) => println!("Successfully inserted new transaction input"),
-                                                                                Err(e) => eprintln!("Error inserting transaction input: {}", e),
-                                                                            }
-                                                                        }
-
-//=========================================================================
-                                                                        for vout in tx.vout {
-                                                                            println!("Processing vout: {:?}", vout);
-                                                                            if let Some(script) = &vout.script_pub_key {
-                                                                                // println!("ScriptPubKey found: {:?}", script);
-
-                                                                                let latest_output: Option<TransactionOutput> = transaction_outputs::table
-                                                                                    .order(transaction_outputs::id.desc())
-                                                                                    .first(&mut conn)
-                                                                                    .optional()
-                                                                                    .expect("Error querying latest transaction output");
-
-                                                                                let new_output_id = latest_output.as_ref().map_or(1, |latest| latest.id + 1);
-
-                                                                                let address = script.addresses.join(", ");
-
-                                                                                let new_output = TransactionOutput {
-                                                                                    id: new_output_id,
-                                                                                    transaction_id: new_tx_id,
-                                                                                    address: address.clone(),
-                                                                                    value: vout.value as i64,
-                                                                                };
-
-                                                                                match diesel::insert_into(transaction_outputs::table)
-                                                                                    .values(&new_output)
-                                                                                    .execute(&mut conn)
-                                                                                {
-                                                                                    Ok(_) => println!("Successfully inserted new transaction output with address: {}", address),
-                                                                                    Err(e) => eprintln!("Error inserting transaction output: {}", e),
-                                                                                }
-                                                                            } else {
-
-                                                                                let latest_output: Option<TransactionOutput> = transaction_outputs::table
-                                                                                    .order(transaction_outputs::id.desc())
-                                                                                    .first(&mut conn)
-                                                                                    .optional()
-                                                                                    .expect("Error querying latest transaction output");
-
-                                                                                let new_output_id = latest_output.as_ref().map_or(1, |latest| latest.id + 1);
-
-                                                                                let new_output = TransactionOutput {
-                                                                                    id: new_output_id,
-                                                                                    transaction_id: new_tx_id,
-                                                                                    address: "".to_string(),
-                                                                                    value: vout.value as i64,
-                                                                                };
-
-                                                                                match diesel::insert_into(transaction_outputs::table)
-                                                                                    .values(&new_output)
-                                                                                    .execute(&mut conn)
-                                                                                {
-                                                                                    Ok(_) => println!("Successfully inserted new transaction output without address"),
-                                                                                    Err(e) => eprintln!("Error inserting transaction output: {}", e),
-                                                                                }
-                                                                            }
-                                                                        }
-
-
-
-
-
-//==========================================================================
-
-                                                                    }
-                                                                }
-                                                            }
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
+    }
+    Ok(())
+}
 
+// Heights the tip worker ingests: everything above the highest stored block, so blocks mined
+// between polls or while the service was down, and blocks a failed reorg replay left out, are not
+// skipped. The tip itself is always looked at, since a block replaced at the same height only
+// shows up there. With nothing stored yet, only the tip is; older blocks are for the backfill.
+fn catch_up_heights(stored: Option<i32>, tip: i32) -> std::ops::RangeInclusive<i32> {
+    stored.map_or(tip, |stored| (stored + 1).min(tip))..=tip
+}
 
+fn highest_stored_height(
+    pool: &r2d2::Pool<ConnectionManager<PgConnection>>,
+) -> Result<Option<i32>, Box<dyn Error + Send + Sync>> {
+    let mut conn = pool.get()?;
+    Ok(block_info::table
+        .filter(block_info::complete.eq(true))
+        .select(diesel::dsl::max(block_info::height))
+        .first::<Option<i32>>(&mut conn)?)
+}
 
+// Walks every height in [start_height, end_height] and ingests it, recording progress in
+// backfill_progress so a restarted backfill with the same start height picks up where it stopped.
+// Without an end height the backfill runs up to the tip at the time it starts.
+async fn backfill_block_info(
+    pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>,
+    source: Arc<dyn BlockSource>,
+    is_fetching: Arc<Mutex<()>>,
+    start_height: i32,
+    end_height: Option<i32>,
+    retry_delay: Duration,
+    mut shutdown: Shutdown,
+) -> WorkerResult {
+
+    let end_height = match end_height {
+        Some(end_height) => end_height,
+        None => loop {
+            match source.tip_height().await {
+                Ok(height) => break height,
+                Err(e) => {
+                    error!(error = %e, "Error fetching tip height for backfill");
+                    tokio::select! {
+                        _ = time::sleep(retry_delay) => {}
+                        _ = shutdown.wait() => return Ok(()),
                     }
                 }
             }
+        },
+    };
+
+    // Without its progress the backfill cannot tell where to resume; the supervisor retries it.
+    let mut next_height = load_backfill_progress(&pool, start_height)
+        .map_err(|e| format!("could not load backfill progress: {}", e))?
+        .map_or(start_height, |next| next.max(start_height));
+
+    info!(start_height, end_height, next_height, "Backfilling blocks");
+
+    while next_height <= end_height {
+        if shutdown.is_triggered() {
+            info!(next_height, "Backfill interrupted, will resume from here");
+            return Ok(());
         }
 
-        *is_fetching_guard = false;
+        let is_fetching_guard = is_fetching.lock().await;
+        let result = ingest_block_at_height(source.as_ref(), pool.clone(), next_height).await;
+        drop(is_fetching_guard);
+
+        match result {
+            Ok(()) => {
+                next_height += 1;
+                if let Err(e) = save_backfill_progress(&pool, start_height, end_height, next_height) {
+                    error!(error = %e, "Error saving backfill progress");
+                }
+            }
+            Err(e) => {
+                error!(height = next_height, error = %e, "Error backfilling block");
+                tokio::select! {
+                    _ = time::sleep(retry_delay) => {}
+                    _ = shutdown.wait() => return Ok(()),
+                }
+            }
+        }
     }
+
+    info!(start_height, end_height, "Backfill complete");
+    Ok(())
+}
+
+fn load_backfill_progress(
+    pool: &r2d2::Pool<ConnectionManager<PgConnection>>,
+    start_height: i32,
+) -> Result<Option<i32>, Box<dyn Error + Send + Sync>> {
+    let mut conn = pool.get()?;
+    Ok(backfill_progress::table
+        .find(start_height)
+        .select(backfill_progress::next_height)
+        .first::<i32>(&mut conn)
+        .optional()?)
+}
+
+fn save_backfill_progress(
+    pool: &r2d2::Pool<ConnectionManager<PgConnection>>,
+    start_height: i32,
+    end_height: i32,
+    next_height: i32,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut conn = pool.get()?;
+    let updated_at = Utc::now().naive_utc();
+    diesel::insert_into(backfill_progress::table)
+        .values((
+            backfill_progress::start_height.eq(start_height),
+            backfill_progress::end_height.eq(end_height),
+            backfill_progress::next_height.eq(next_height),
+            backfill_progress::updated_at.eq(updated_at),
+        ))
+        .on_conflict(backfill_progress::start_height)
+        .do_update()
+        .set((
+            backfill_progress::end_height.eq(end_height),
+            backfill_progress::next_height.eq(next_height),
+            backfill_progress::updated_at.eq(updated_at),
+        ))
+        .execute(&mut conn)?;
+    Ok(())
 }
 
+type Sources = (Arc<dyn BlockSource>, Option<Arc<dyn MempoolSource>>);
+
+// Builds the configured block source. Esplora is given a list of mirrors, tried in order, instead
+// of just blockstream.info; the blkfiles source reads a copy of the datadir's blocks/ directory for
+// the configured network. Esplora and bitcoind also serve the mempool; block files have none, so
+// the mempool poller is off.
+fn sources(
+    config: &Config,
+    http: &Arc<http::HttpClient>,
+) -> Result<Sources, Box<dyn Error + Send + Sync>> {
+    Ok(match &config.source {
+        SourceConfig::Esplora { urls } => {
+            let source = Arc::new(EsploraSource::new(Arc::clone(http), urls));
+            (source.clone(), Some(source))
+        }
+        SourceConfig::Bitcoind { url, auth } => {
+            let source = Arc::new(BitcoindSource::new(Arc::clone(http), url, auth.clone()));
+            (source.clone(), Some(source))
+        }
+        SourceConfig::BlkFiles { blocks_dir } => {
+            let source = BlkFileSource::open(blocks_dir, config.network)
+                .map_err(|e| format!("could not index block files in {}: {}", blocks_dir, e))?;
+            (Arc::new(source), None)
+        }
+    })
+}
+
+fn stored_block_hash(
+    conn: &mut PgConnection,
+    height: i32,
+) -> Result<Option<Option<String>>, diesel::result::Error> {
+    block_info::table
+        .filter(block_info::height.eq(height))
+        .select(block_info::hash)
+        .first::<Option<String>>(conn)
+        .optional()
+}
+
+// Returns the highest height at which the stored chain still agrees with upstream when the
+// block being ingested does not build on what we have stored, or None if no rollback is needed.
+// Rows ingested before hashes were recorded have no hash and are taken as matching.
+async fn find_fork_height(
+    source: &dyn BlockSource,
+    pool: &r2d2::Pool<ConnectionManager<PgConnection>>,
+    api_block_info: &ApiBlockInfo,
+) -> Result<Option<i32>, Box<dyn Error + Send + Sync>> {
+    let mut conn = pool.get()?;
+
+    let stored_at_height = stored_block_hash(&mut conn, api_block_info.height)?.flatten();
+    let mut diverged = stored_at_height.is_some_and(|hash| hash != api_block_info.id);
+
+    let mut height = api_block_info.height - 1;
+    let mut expected_hash = api_block_info.previousblockhash.clone();
+
+    while let Some(expected) = expected_hash {
+        match stored_block_hash(&mut conn, height)?.flatten() {
+            Some(stored) if stored != expected => {
+                diverged = true;
+                expected_hash = source.block(&expected).await?.previousblockhash;
+                height -= 1;
+            }
+            _ => break,
+        }
+    }
+
+    Ok(if diverged { Some(height) } else { None })
+}
+
+// Deletes every stored block above fork_height together with its transactions, inputs and outputs.
+fn rollback_blocks_above(
+    pool: &r2d2::Pool<ConnectionManager<PgConnection>>,
+    fork_height: i32,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut conn = pool.get()?;
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        utxo::disconnect_blocks_above(conn, fork_height)?;
+        address::disconnect_blocks_above(conn, fork_height)?;
+        mempool::unconfirm_blocks_above(conn, fork_height)?;
+        block_price::disconnect_blocks_above(conn, fork_height)?;
+
+        let orphaned_txs = transactions::table
+            .filter(transactions::block_height.gt(fork_height))
+            .select(transactions::id);
+
+        diesel::delete(transaction_inputs::table.filter(transaction_inputs::transaction_id.eq_any(orphaned_txs)))
+            .execute(conn)?;
+        diesel::delete(transaction_outputs::table.filter(transaction_outputs::transaction_id.eq_any(orphaned_txs)))
+            .execute(conn)?;
+        diesel::delete(transactions::table.filter(transactions::block_height.gt(fork_height)))
+            .execute(conn)?;
+        let removed = diesel::delete(block_info::table.filter(block_info::height.gt(fork_height)))
+            .execute(conn)?;
+
+        info!(removed, fork_height, "Rolled back orphaned blocks");
+        Ok(())
+    })?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "block", skip(source, pool))]
+async fn ingest_block_at_height(
+    source: &dyn BlockSource,
+    pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>,
+    height: i32,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let hash = source.block_hash(height).await?;
+    let api_block_info = source.block(&hash).await?;
+
+    if let Some(fork_height) = find_fork_height(source, &pool, &api_block_info).await? {
+        warn!(fork_height, "Chain reorganization detected");
+        rollback_blocks_above(&pool, fork_height)?;
+
+        for branch_height in (fork_height + 1)..height {
+            let branch_hash = source.block_hash(branch_height).await?;
+            let branch_block = source.block(&branch_hash).await?;
+            store_block(source, &pool, &branch_block).await?;
+        }
+    }
+
+    store_block(source, &pool, &api_block_info).await
+}
+
+// Rows per multi-row INSERT, keeping statements well under Postgres' 65535 bind parameter limit.
+const INSERT_CHUNK_SIZE: usize = 1000;
+
+// Values of the outputs spent by `txs`, by txid and vout. Sources without prevouts (block files)
+// leave them to be found among the block's own outputs and the outputs already stored; a backfill
+// runs in height order, so those are normally there. Whatever is still missing is unknown.
+fn spent_values(conn: &mut PgConnection, txs: &[ApiTransaction]) -> QueryResult<HashMap<(String, u32), i64>> {
+    let mut values = HashMap::new();
+    for tx in txs {
+        for (vout, output) in tx.vout.iter().enumerate() {
+            values.insert((tx.txid.clone(), vout as u32), output.value);
+        }
+        for vin in &tx.vin {
+            if let Some(prevout) = &vin.prevout {
+                values.insert((vin.txid.clone(), vin.vout), prevout.value);
+            }
+        }
+    }
+
+    let missing: HashSet<&String> = txs
+        .iter()
+        .flat_map(|tx| &tx.vin)
+        .filter(|vin| !vin.is_coinbase && !values.contains_key(&(vin.txid.clone(), vin.vout)))
+        .map(|vin| &vin.txid)
+        .collect();
+    if !missing.is_empty() {
+        let stored: Vec<(String, i32, i64)> = transaction_outputs::table
+            .inner_join(transactions::table)
+            .filter(transactions::hash.eq_any(missing))
+            .select((transactions::hash, transaction_outputs::vout, transaction_outputs::value))
+            .load(conn)?;
+        values.extend(stored.into_iter().map(|(txid, vout, value)| ((txid, vout as u32), value)));
+    }
+    Ok(values)
+}
+
+// A coinbase input spends nothing and is stored with value 0, as Esplora reports it.
+fn input_value(vin: &ApiTransactionInput, spent: &HashMap<(String, u32), i64>) -> Option<i64> {
+    if vin.is_coinbase {
+        Some(0)
+    } else {
+        spent.get(&(vin.txid.clone(), vin.vout)).copied()
+    }
+}
+
+// The source's fee, or else what the inputs bring in over what the outputs pay out, once every
+// input's value is known.
+fn fee(tx: &ApiTransaction, spent: &HashMap<(String, u32), i64>) -> Option<i64> {
+    tx.fee.or_else(|| {
+        let inputs = tx.vin.iter().map(|vin| input_value(vin, spent)).sum::<Option<i64>>()?;
+        Some(inputs - tx.vout.iter().map(|vout| vout.value).sum::<i64>())
+    })
+}
+
+#[tracing::instrument(name = "store", skip_all, fields(height = api_block_info.height, hash = %api_block_info.id))]
+async fn store_block(
+    source: &dyn BlockSource,
+    pool: &r2d2::Pool<ConnectionManager<PgConnection>>,
+    api_block_info: &ApiBlockInfo,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let existing_block: Option<BlockInfo> = block_info::table
+        .filter(block_info::height.eq(api_block_info.height))
+        .first(&mut pool.get()?)
+        .optional()?;
+
+    if existing_block.as_ref().is_some_and(|block| block.complete && block.hash.as_ref() == Some(&api_block_info.id)) {
+        debug!("Block already stored");
+        return Ok(());
+    }
+
+    let txs = source.block_txs(api_block_info).await?;
+
+    // Only a block whose every transaction made it into the database counts as complete.
+    if txs.len() != api_block_info.tx_count as usize {
+        return Err(format!(
+            "Block {} at height {} has {} transactions but only {} were fetched",
+            api_block_info.id, api_block_info.height, api_block_info.tx_count, txs.len()
+        ).into());
+    }
+
+    // The whole block is written in one database transaction, so a failure part way through
+    // leaves nothing behind and the next attempt starts from a clean slate.
+    let mut conn = pool.get()?;
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        if let Some(existing_block) = existing_block {
+            if existing_block.hash.is_none() {
+                diesel::update(block_info::table.find(existing_block.id))
+                    .set((
+                        block_info::hash.eq(&api_block_info.id),
+                        block_info::previous_hash.eq(&api_block_info.previousblockhash),
+                    ))
+                    .execute(conn)?;
+            }
+        } else {
+            let timestamp = Utc.timestamp_opt(api_block_info.timestamp, 0).unwrap();
+
+            let new_info = NewBlockInfo {
+                height: api_block_info.height,
+                avg_tx_count: api_block_info.tx_count,
+                difficulty: api_block_info.difficulty,
+                block_time: api_block_info.mediantime as i32,
+                timestamp: timestamp.naive_utc(),
+                size: api_block_info.size,
+                weight: api_block_info.weight,
+                hash: Some(api_block_info.id.clone()),
+                previous_hash: api_block_info.previousblockhash.clone(),
+                complete: false,
+            };
+
+            diesel::insert_into(block_info::table)
+                .values(&new_info)
+                .execute(conn)?;
+        }
+
+        let spent = spent_values(conn, &txs)?;
+
+        let new_txs: Vec<NewTransaction> = txs.iter().map(|tx| NewTransaction {
+            block_height: api_block_info.height,
+            hash: tx.txid.clone(),
+            btc: tx.vout.iter().map(|vout| vout.value).sum::<i64>() as f64 / 100_000_000.0,
+            fee: fee(tx, &spent),
+            time: api_block_info.timestamp,
+            vsize: (tx.weight + 3) / 4,
+            weight: tx.weight,
+        }).collect();
+
+        // Transactions that are already stored were written together with their inputs and
+        // outputs, so only the ones inserted now (and returned here) need them.
+        let mut tx_ids: HashMap<String, i32> = HashMap::new();
+        for chunk in new_txs.chunks(INSERT_CHUNK_SIZE) {
+            let inserted: Vec<(i32, String)> = diesel::insert_into(transactions::table)
+                .values(chunk)
+                .on_conflict((transactions::hash, transactions::block_height))
+                .do_nothing()
+                .returning((transactions::id, transactions::hash))
+                .get_results(conn)?;
+            tx_ids.extend(inserted.into_iter().map(|(id, hash)| (hash, id)));
+        }
+
+        let mut new_inputs = Vec::new();
+        let mut new_outputs = Vec::new();
+        for tx in &txs {
+            let Some(&transaction_id) = tx_ids.get(&tx.txid) else {
+                continue;
+            };
+
+            for (index, vin) in tx.vin.iter().enumerate() {
+                new_inputs.push(NewTransactionInput {
+                    transaction_id,
+                    previous_output: vin.txid.clone(),
+                    value: input_value(vin, &spent),
+                    vin: index as i32,
+                    // A coinbase input spends no outpoint; Esplora reports its vout as 0xffffffff.
+                    previous_vout: (!vin.is_coinbase).then_some(vin.vout as i32),
+                    sequence: vin.sequence,
+                    scriptsig: vin.scriptsig.clone(),
+                    witness: vin.witness.clone(),
+                    is_coinbase: vin.is_coinbase,
+                });
+            }
+
+            for (index, vout) in tx.vout.iter().enumerate() {
+                new_outputs.push(NewTransactionOutput {
+                    transaction_id,
+                    address: vout.scriptpubkey_address.clone().unwrap_or_default(),
+                    value: vout.value,
+                    vout: index as i32,
+                    script_hex: vout.scriptpubkey.clone(),
+                    script_asm: vout.scriptpubkey_asm.clone(),
+                    script_type: vout.scriptpubkey_type.clone(),
+                });
+            }
+        }
+
+        for chunk in new_inputs.chunks(INSERT_CHUNK_SIZE) {
+            diesel::insert_into(transaction_inputs::table)
+                .values(chunk)
+                .on_conflict((transaction_inputs::transaction_id, transaction_inputs::vin))
+                .do_nothing()
+                .execute(conn)?;
+        }
+
+        for chunk in new_outputs.chunks(INSERT_CHUNK_SIZE) {
+            diesel::insert_into(transaction_outputs::table)
+                .values(chunk)
+                .on_conflict((transaction_outputs::transaction_id, transaction_outputs::vout))
+                .do_nothing()
+                .execute(conn)?;
+        }
+
+        utxo::connect_block(conn, api_block_info.height)?;
+        address::connect_transactions(conn, &tx_ids.values().copied().collect::<Vec<_>>())?;
+
+        let txids: Vec<String> = txs.iter().map(|tx| tx.txid.clone()).collect();
+        mempool::confirm_transactions(conn, api_block_info.height, &txids)?;
+
+        diesel::update(block_info::table.filter(block_info::height.eq(api_block_info.height)))
+            .set(block_info::complete.eq(true))
+            .execute(conn)?;
+
+        Ok(())
+    })?;
+
+    metrics::BLOCKS_INGESTED.inc();
+    metrics::TRANSACTIONS_INGESTED.inc_by(txs.len() as u64);
+    info!(transactions = txs.len(), "Stored block");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn currency(requested: Option<&str>) -> Result<String, ApiError> {
+        let tracked = vec!["eur".to_string(), "usd".to_string()];
+        CurrencyQuery { currency: requested.map(str::to_string) }.currency(&tracked)
+    }
+
+    fn transaction(txid: &str, inputs: &[(&str, u32)], outputs: &[i64], fee: Option<i64>) -> ApiTransaction {
+        let output = |value: &i64| serde_json::json!({
+            "scriptpubkey": "", "scriptpubkey_asm": "", "scriptpubkey_type": "unknown", "value": value,
+        });
+        serde_json::from_value(serde_json::json!({
+            "txid": txid,
+            "fee": fee,
+            "weight": 400,
+            "vin": inputs.iter().map(|(txid, vout)| serde_json::json!({
+                "txid": txid, "vout": vout, "prevout": null, "scriptsig": "", "is_coinbase": false, "sequence": 0,
+            })).collect::<Vec<_>>(),
+            "vout": outputs.iter().map(output).collect::<Vec<_>>(),
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn works_out_fees_from_the_spent_values() {
+        let spent = HashMap::from([(("a".to_string(), 0), 5_000), (("a".to_string(), 1), 3_000)]);
+
+        assert_eq!(fee(&transaction("b", &[("a", 0), ("a", 1)], &[7_500], None), &spent), Some(500));
+        // The source's own fee is taken as is.
+        assert_eq!(fee(&transaction("b", &[("a", 0)], &[4_000], Some(1_000)), &spent), Some(1_000));
+        // One unknown input leaves the fee unknown rather than counting it as 0.
+        assert_eq!(fee(&transaction("b", &[("a", 0), ("c", 0)], &[4_000], None), &spent), None);
+        assert_eq!(input_value(&transaction("b", &[("c", 0)], &[], None).vin[0], &spent), None);
+    }
+
+    #[test]
+    fn catches_up_from_the_highest_stored_block() {
+        assert_eq!(catch_up_heights(Some(100), 100), 100..=100);
+        assert_eq!(catch_up_heights(Some(99), 100), 100..=100);
+        // Blocks mined between polls or while down.
+        assert_eq!(catch_up_heights(Some(95), 100), 96..=100);
+        // Stored above a lagging source's tip: only the tip is checked.
+        assert_eq!(catch_up_heights(Some(102), 100), 100..=100);
+        assert_eq!(catch_up_heights(None, 100), 100..=100);
+    }
+
+    #[test]
+    fn accepts_only_tracked_currencies() {
+        assert_eq!(currency(None).unwrap(), "eur");
+        assert_eq!(currency(Some("usd")).unwrap(), "usd");
+        assert_eq!(currency(Some(" USD ")).unwrap(), "usd");
+        assert!(matches!(currency(Some("gbp")), Err(ApiError::BadRequest(_))));
+        assert!(matches!(currency(Some("")), Err(ApiError::BadRequest(_))));
+    }
+}
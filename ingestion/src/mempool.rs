@@ -0,0 +1,364 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager};
+use diesel::sql_types::BigInt;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::Arc;
+use tokio::time::{self, Duration};
+use tracing::{debug, error, info};
+
+use crate::error::{reject, with_connection, CONNECTION_TIMEOUT};
+use crate::schema::{block_info, mempool_transactions};
+use crate::source::MempoolSource;
+use crate::supervisor::{Shutdown, WorkerResult};
+use crate::PageQuery;
+
+// New transactions looked up per poll; the rest are picked up on later polls.
+const MEMPOOL_FETCH_LIMIT: usize = 200;
+// Unconfirmed transactions tracked at most; beyond this, new ones are not stored until some leave.
+const MAX_TRACKED: usize = 100_000;
+// A transaction that left the upstream mempool is only dropped as evicted after being missing
+// for more polls than this and once a block has been stored since, so one that was mined is
+// confirmed by its block instead.
+const EVICT_AFTER_POLLS: u32 = 6;
+// Confirmed entries are kept this many blocks deep so a reorg can put them back in the mempool.
+const CONFIRMED_RETENTION: i32 = 6;
+
+#[derive(Queryable)]
+pub struct MempoolTransaction {
+    pub txid: String,
+    pub fee: i64,
+    pub vsize: i64,
+    pub weight: i64,
+    pub first_seen: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = mempool_transactions)]
+pub struct NewMempoolTransaction {
+    pub txid: String,
+    pub fee: i64,
+    pub vsize: i64,
+    pub weight: i64,
+    pub first_seen: NaiveDateTime,
+}
+
+// A transaction that is stored but no longer in the upstream mempool.
+struct Missing {
+    polls: u32,
+    // Highest stored block when it was first found missing.
+    tip: Option<i32>,
+}
+
+// What the poller keeps between polls: the unconfirmed txids it has stored, so each poll diffs
+// against memory rather than the table, and how long each one has been missing upstream.
+#[derive(Default)]
+struct Tracked {
+    known: HashSet<String>,
+    missing: HashMap<String, Missing>,
+}
+
+impl Tracked {
+    fn load(conn: &mut PgConnection) -> QueryResult<Self> {
+        let known = mempool_transactions::table
+            .filter(mempool_transactions::confirmed_height.is_null())
+            .select(mempool_transactions::txid)
+            .limit(MAX_TRACKED as i64)
+            .load::<String>(conn)?
+            .into_iter()
+            .collect();
+        Ok(Tracked { known, missing: HashMap::new() })
+    }
+
+    // Txids in `current` that are not tracked yet, as many as may be looked up now.
+    fn new_txids(&self, current: &[String]) -> Vec<String> {
+        let room = MAX_TRACKED.saturating_sub(self.known.len()).min(MEMPOOL_FETCH_LIMIT);
+        current.iter().filter(|txid| !self.known.contains(*txid)).take(room).cloned().collect()
+    }
+
+    // Counts another poll for every tracked txid missing from `current` and returns those that
+    // have now been missing long enough to be dropped; they are no longer tracked.
+    fn evicted(&mut self, current: &HashSet<&String>, tip: Option<i32>) -> Vec<String> {
+        self.missing.retain(|txid, _| !current.contains(txid));
+        for txid in self.known.iter().filter(|txid| !current.contains(txid)) {
+            self.missing.entry(txid.clone()).or_insert(Missing { polls: 0, tip }).polls += 1;
+        }
+
+        let evicted: Vec<String> = self
+            .missing
+            .iter()
+            .filter(|(_, missing)| missing.polls > EVICT_AFTER_POLLS && missing.tip < tip)
+            .map(|(txid, _)| txid.clone())
+            .collect();
+        for txid in &evicted {
+            self.known.remove(txid);
+            self.missing.remove(txid);
+        }
+        evicted
+    }
+}
+
+pub async fn fetch_and_store_mempool(
+    pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>,
+    source: Arc<dyn MempoolSource>,
+    poll_interval: Duration,
+    mut shutdown: Shutdown,
+) -> WorkerResult {
+    let mut interval = time::interval(poll_interval);
+    let mut tracked = None;
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.wait() => break,
+        }
+
+        if let Err(e) = sync_mempool(&pool, source.as_ref(), &mut tracked).await {
+            error!(error = %e, "Error syncing mempool");
+            // What was tracked may no longer match the table; start over from it.
+            tracked = None;
+        }
+    }
+    Ok(())
+}
+
+async fn sync_mempool(
+    pool: &r2d2::Pool<ConnectionManager<PgConnection>>,
+    source: &dyn MempoolSource,
+    tracked: &mut Option<Tracked>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let txids = source.mempool_txids().await?;
+
+    let tracked = match tracked {
+        Some(tracked) => tracked,
+        None => {
+            let mut conn = pool.get()?;
+            tracked.insert(Tracked::load(&mut conn)?)
+        }
+    };
+
+    let new_txids = tracked.new_txids(&txids);
+    let entries = source.mempool_entries(&new_txids).await?;
+
+    let first_seen = Utc::now().naive_utc();
+    let new_rows: Vec<NewMempoolTransaction> = entries
+        .into_iter()
+        .map(|entry| NewMempoolTransaction {
+            txid: entry.txid,
+            fee: entry.fee,
+            vsize: entry.vsize,
+            weight: entry.weight,
+            first_seen,
+        })
+        .collect();
+
+    let mut conn = pool.get()?;
+    let tip: Option<i32> = block_info::table.select(diesel::dsl::max(block_info::height)).first(&mut conn)?;
+    let current: HashSet<&String> = txids.iter().collect();
+    let gone = tracked.evicted(&current, tip);
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        for chunk in new_rows.chunks(1000) {
+            diesel::insert_into(mempool_transactions::table)
+                .values(chunk)
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+        }
+
+        // Unconfirmed transactions that left the mempool were evicted or replaced.
+        for chunk in gone.chunks(1000) {
+            diesel::delete(
+                mempool_transactions::table
+                    .filter(mempool_transactions::confirmed_height.is_null())
+                    .filter(mempool_transactions::txid.eq_any(chunk)),
+            )
+            .execute(conn)?;
+        }
+
+        if let Some(tip) = tip {
+            diesel::delete(
+                mempool_transactions::table.filter(mempool_transactions::confirmed_height.le(tip - CONFIRMED_RETENTION)),
+            )
+            .execute(conn)?;
+        }
+
+        Ok(())
+    })?;
+    let new = new_rows.len();
+    tracked.known.extend(new_rows.into_iter().map(|row| row.txid));
+
+    info!(upstream = txids.len(), new, removed = gone.len(), "Mempool synced");
+    Ok(())
+}
+
+// Marks mempool entries that were mined in the block at `height` as confirmed.
+pub fn confirm_transactions(conn: &mut PgConnection, height: i32, txids: &[String]) -> QueryResult<()> {
+    diesel::update(mempool_transactions::table.filter(mempool_transactions::txid.eq_any(txids)))
+        .set(mempool_transactions::confirmed_height.eq(height))
+        .execute(conn)?;
+    Ok(())
+}
+
+// Puts transactions from orphaned blocks back into the mempool.
+pub fn unconfirm_blocks_above(conn: &mut PgConnection, fork_height: i32) -> QueryResult<()> {
+    diesel::update(mempool_transactions::table.filter(mempool_transactions::confirmed_height.gt(fork_height)))
+        .set(mempool_transactions::confirmed_height.eq(None::<i32>))
+        .execute(conn)?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct PendingTransaction {
+    pub txid: String,
+    pub fee: i64,
+    pub vsize: i64,
+    pub weight: i64,
+    // sat/vB
+    pub fee_rate: f64,
+    pub first_seen: NaiveDateTime,
+}
+
+#[derive(Serialize)]
+pub struct MempoolPage {
+    pub page: i64,
+    pub per_page: i64,
+    pub transactions: Vec<PendingTransaction>,
+}
+
+#[derive(QueryableByName, Serialize)]
+pub struct MempoolSummary {
+    #[diesel(sql_type = BigInt)]
+    pub count: i64,
+    #[diesel(sql_type = BigInt)]
+    pub vsize: i64,
+    #[diesel(sql_type = BigInt)]
+    pub total_fee: i64,
+}
+
+pub async fn handle_get_mempool(
+    pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    debug!("Handling get mempool...");
+    let summary: MempoolSummary = with_connection(pool, CONNECTION_TIMEOUT, |conn| {
+        Ok(diesel::sql_query(
+            "SELECT COUNT(*) AS count,
+                    COALESCE(SUM(vsize), 0)::BIGINT AS vsize,
+                    COALESCE(SUM(fee), 0)::BIGINT AS total_fee
+             FROM mempool_transactions
+             WHERE confirmed_height IS NULL",
+        )
+        .get_result(conn)?)
+    })
+    .await
+    .map_err(reject)?;
+
+    Ok(warp::reply::json(&summary))
+}
+
+pub async fn handle_get_mempool_txs(
+    pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>,
+    query: PageQuery,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    debug!("Handling get mempool transactions...");
+    let page = query.page();
+    let per_page = query.per_page();
+    let results: Vec<MempoolTransaction> = with_connection(pool, CONNECTION_TIMEOUT, move |conn| {
+        Ok(mempool_transactions::table
+            .filter(mempool_transactions::confirmed_height.is_null())
+            .select((
+                mempool_transactions::txid,
+                mempool_transactions::fee,
+                mempool_transactions::vsize,
+                mempool_transactions::weight,
+                mempool_transactions::first_seen,
+            ))
+            .order(mempool_transactions::first_seen.desc())
+            .limit(per_page)
+            .offset((page - 1) * per_page)
+            .load(conn)?)
+    })
+    .await
+    .map_err(reject)?;
+
+    let transactions: Vec<PendingTransaction> = results.into_iter().map(|tx| PendingTransaction {
+        fee_rate: tx.fee as f64 / tx.vsize.max(1) as f64,
+        txid: tx.txid,
+        fee: tx.fee,
+        vsize: tx.vsize,
+        weight: tx.weight,
+        first_seen: tx.first_seen,
+    }).collect();
+
+    Ok(warp::reply::json(&MempoolPage { page, per_page, transactions }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracked(txids: &[&str]) -> Tracked {
+        Tracked { known: txids.iter().map(|txid| txid.to_string()).collect(), missing: HashMap::new() }
+    }
+
+    fn poll(tracked: &mut Tracked, current: &[&str], tip: Option<i32>) -> Vec<String> {
+        let current: Vec<String> = current.iter().map(|txid| txid.to_string()).collect();
+        let mut evicted = tracked.evicted(&current.iter().collect(), tip);
+        evicted.sort();
+        evicted
+    }
+
+    #[test]
+    fn keeps_missing_transactions_until_a_block_is_stored() {
+        let mut tracked = tracked(&["a", "b"]);
+        for _ in 0..=EVICT_AFTER_POLLS + 3 {
+            assert!(poll(&mut tracked, &["b"], Some(100)).is_empty());
+        }
+        assert_eq!(poll(&mut tracked, &["b"], Some(101)), vec!["a".to_string()]);
+        assert_eq!(tracked.known, HashSet::from(["b".to_string()]));
+        assert!(tracked.missing.is_empty());
+    }
+
+    #[test]
+    fn keeps_missing_transactions_for_the_grace_polls() {
+        let mut tracked = tracked(&["a"]);
+        for _ in 0..EVICT_AFTER_POLLS {
+            assert!(poll(&mut tracked, &[], Some(101)).is_empty());
+        }
+        assert_eq!(poll(&mut tracked, &[], Some(101)), Vec::<String>::new());
+        assert_eq!(poll(&mut tracked, &[], Some(102)), vec!["a".to_string()]);
+    }
+
+    #[test]
+    fn forgets_transactions_that_come_back() {
+        let mut tracked = tracked(&["a"]);
+        for _ in 0..EVICT_AFTER_POLLS {
+            poll(&mut tracked, &[], Some(100));
+        }
+        assert!(poll(&mut tracked, &["a"], Some(100)).is_empty());
+        assert!(tracked.missing.is_empty());
+        assert!(poll(&mut tracked, &[], Some(101)).is_empty());
+    }
+
+    #[test]
+    fn counts_an_empty_chain_as_below_any_tip() {
+        let mut tracked = tracked(&["a"]);
+        for _ in 0..=EVICT_AFTER_POLLS {
+            assert!(poll(&mut tracked, &[], None).is_empty());
+        }
+        assert_eq!(poll(&mut tracked, &[], Some(0)), vec!["a".to_string()]);
+    }
+
+    #[test]
+    fn looks_up_only_untracked_transactions_within_the_limits() {
+        let tracked = tracked(&["a"]);
+        let current: Vec<String> = (0..MEMPOOL_FETCH_LIMIT + 10).map(|i| i.to_string()).chain(["a".to_string()]).collect();
+        let new = tracked.new_txids(&current);
+        assert_eq!(new.len(), MEMPOOL_FETCH_LIMIT);
+        assert!(!new.contains(&"a".to_string()));
+
+        let full = Tracked { known: (0..MAX_TRACKED).map(|i| format!("k{}", i)).collect(), missing: HashMap::new() };
+        assert!(full.new_txids(&current).is_empty());
+    }
+}
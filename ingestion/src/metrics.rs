@@ -0,0 +1,201 @@
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager};
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    Encoder, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use std::sync::{Arc, LazyLock};
+use tracing::{debug, warn};
+
+use crate::error::{reject, with_connection, ApiError, CONNECTION_TIMEOUT};
+use crate::schema::block_info;
+
+// Everything lives in the default registry and is served as text on GET /metrics.
+
+pub static INGESTED_TIP_HEIGHT: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("ingestion_ingested_tip_height", "Height of the highest completely stored block").unwrap()
+});
+
+pub static UPSTREAM_TIP_HEIGHT: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("ingestion_upstream_tip_height", "Tip height last reported by the block source").unwrap()
+});
+
+pub static BLOCKS_INGESTED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("ingestion_blocks_ingested_total", "Blocks stored, including reorg replacements").unwrap()
+});
+
+pub static TRANSACTIONS_INGESTED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("ingestion_transactions_ingested_total", "Transactions in the blocks stored").unwrap()
+});
+
+pub static UPSTREAM_REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "ingestion_upstream_request_duration_seconds",
+        "Time taken by each upstream HTTP attempt, retries included as separate attempts",
+        &["host"]
+    )
+    .unwrap()
+});
+
+pub static UPSTREAM_RETRIES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("ingestion_upstream_retries_total", "Upstream requests retried after a transient failure", &["host"]).unwrap()
+});
+
+pub static UPSTREAM_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "ingestion_upstream_errors_total",
+        "Upstream requests that failed after any retries, by host and error kind",
+        &["host", "kind"]
+    )
+    .unwrap()
+});
+
+pub static DB_POOL_CONNECTIONS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("ingestion_db_pool_connections", "Connections open in the database pool").unwrap()
+});
+
+pub static DB_POOL_IDLE_CONNECTIONS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("ingestion_db_pool_idle_connections", "Open database connections not checked out").unwrap()
+});
+
+pub static DB_POOL_MAX_CONNECTIONS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("ingestion_db_pool_max_connections", "Configured size of the database pool").unwrap()
+});
+
+pub static PRICE_FETCHES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "ingestion_price_fetches_total",
+        "Price quote requests by provider and outcome (success or error)",
+        &["provider", "outcome"]
+    )
+    .unwrap()
+});
+
+pub static PRICE_OUTLIERS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("ingestion_price_outliers_total", "Quotes left out of the aggregate as outliers", &["provider"]).unwrap()
+});
+
+pub static HTTP_REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "ingestion_http_request_duration_seconds",
+        "Time taken to answer API requests, by route, method and status",
+        &["route", "method", "status"]
+    )
+    .unwrap()
+});
+
+pub static WORKER_UP: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!("ingestion_worker_up", "1 while a background worker is running, 0 otherwise", &["worker"]).unwrap()
+});
+
+pub static WORKER_RESTARTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("ingestion_worker_restarts_total", "Background workers restarted after a crash", &["worker"]).unwrap()
+});
+
+// Registers every metric up front so each one is exported from the first scrape, before
+// anything has touched it.
+pub fn register() {
+    LazyLock::force(&INGESTED_TIP_HEIGHT);
+    LazyLock::force(&UPSTREAM_TIP_HEIGHT);
+    LazyLock::force(&BLOCKS_INGESTED);
+    LazyLock::force(&TRANSACTIONS_INGESTED);
+    LazyLock::force(&UPSTREAM_REQUEST_DURATION);
+    LazyLock::force(&UPSTREAM_RETRIES);
+    LazyLock::force(&UPSTREAM_ERRORS);
+    LazyLock::force(&DB_POOL_CONNECTIONS);
+    LazyLock::force(&DB_POOL_IDLE_CONNECTIONS);
+    LazyLock::force(&DB_POOL_MAX_CONNECTIONS);
+    LazyLock::force(&PRICE_FETCHES);
+    LazyLock::force(&PRICE_OUTLIERS);
+    LazyLock::force(&HTTP_REQUEST_DURATION);
+    LazyLock::force(&WORKER_UP);
+    LazyLock::force(&WORKER_RESTARTS);
+}
+
+// Route names for the API paths. Raw paths are not used as labels since /block/{height} and
+// /address/{address} would give one series per block and address.
+fn route_name(path: &str) -> &'static str {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["block-info", ..] => "block_info",
+        ["block", _] => "block_detail",
+        ["offchain-data", ..] => "offchain_data",
+        ["utxo-set", ..] => "utxo_set",
+        ["address", _] => "address",
+        ["mempool"] => "mempool",
+        ["mempool", "txs"] => "mempool_txs",
+        ["fees", "recommended"] => "recommended_fees",
+        ["metrics"] => "metrics",
+        ["health"] => "health",
+        ["ready"] => "ready",
+        _ => "other",
+    }
+}
+
+// Times every API reply, rejections included, by route, method and status.
+pub fn track() -> warp::log::Log<impl Fn(warp::log::Info<'_>) + Copy> {
+    warp::log::custom(|info| {
+        HTTP_REQUEST_DURATION
+            .with_label_values(&[route_name(info.path()), info.method().as_str(), info.status().as_str()])
+            .observe(info.elapsed().as_secs_f64());
+    })
+}
+
+pub async fn handle_get_metrics(
+    pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    debug!("Handling get metrics...");
+
+    let state = pool.state();
+    DB_POOL_CONNECTIONS.set(state.connections as i64);
+    DB_POOL_IDLE_CONNECTIONS.set(state.idle_connections as i64);
+    DB_POOL_MAX_CONNECTIONS.set(pool.max_size() as i64);
+
+    // Read at scrape time so rollbacks and backfills are reflected without extra bookkeeping. A
+    // database outage leaves the last value in place rather than failing the scrape.
+    let tip = with_connection(pool, CONNECTION_TIMEOUT, |conn| {
+        Ok(block_info::table
+            .filter(block_info::complete.eq(true))
+            .select(diesel::dsl::max(block_info::height))
+            .first::<Option<i32>>(conn)?)
+    })
+    .await;
+    match tip {
+        Ok(Some(height)) => INGESTED_TIP_HEIGHT.set(height as i64),
+        Ok(None) => {}
+        Err(e) => warn!(error = %e, "Error loading ingested tip height for metrics"),
+    }
+
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    encoder
+        .encode(&prometheus::gather(), &mut body)
+        .map_err(|e| reject(ApiError::Internal(format!("Error encoding metrics: {}", e))))?;
+
+    Ok(warp::reply::with_header(body, "content-type", encoder.format_type()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn collapses_paths_into_one_label_per_route() {
+        let labels = |paths: &[&str]| paths.iter().map(|path| route_name(path)).collect::<HashSet<_>>();
+
+        let blocks = labels(&["/block/0", "/block/840000", "/block/840001/"]);
+        assert_eq!(blocks, HashSet::from(["block_detail"]));
+
+        let addresses = labels(&[
+            "/address/1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa",
+            "/address/bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq",
+            "/address/not-an-address",
+        ]);
+        assert_eq!(addresses, HashSet::from(["address"]));
+
+        // Scans and typos share one series instead of adding a label per path.
+        let unknown = labels(&["/", "/wp-login.php", "/block", "/block/1/txs", "/address/a/b", "/mempool/txs/1"]);
+        assert_eq!(unknown, HashSet::from(["other"]));
+    }
+}
@@ -0,0 +1,63 @@
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use crate::http::HttpClient;
+use super::{parse_number, PriceProvider, PriceQuote, PriceResult};
+
+// Bitstamp's v2 ticker for BTC against the requested currency (btcusd, btceur, ...). Any exchange
+// serving the same last/high/low/volume JSON can be used by pointing BITSTAMP_URL at it.
+pub struct BitstampProvider {
+    http: Arc<HttpClient>,
+    base_url: String,
+}
+
+impl BitstampProvider {
+    pub fn new(http: Arc<HttpClient>, base_url: &str) -> Self {
+        BitstampProvider {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl PriceProvider for BitstampProvider {
+    fn name(&self) -> &'static str {
+        "bitstamp"
+    }
+
+    async fn quote(&self, currency: &str) -> PriceResult<PriceQuote> {
+        let ticker_url = format!("{}/api/v2/ticker/btc{}/", self.base_url, currency.to_lowercase());
+        let ticker = self.http.get_json::<serde_json::Value>(&ticker_url).await?;
+
+        let price = parse_number(&ticker["last"]).ok_or("Bitstamp ticker has no last price")?;
+        Ok(PriceQuote {
+            price,
+            volume: parse_number(&ticker["volume"]).map(|volume| volume * price),
+            high: parse_number(&ticker["high"]),
+            low: parse_number(&ticker["low"]),
+            market_cap: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use warp::http::StatusCode;
+
+    const TICKER: &str = r#"{"last":"65000.5","high":"66000.0","low":"64000.0","volume":"200.0"}"#;
+
+    #[tokio::test]
+    async fn quotes_the_requested_currency() {
+        let (url, requests) = test_util::serve_response(StatusCode::OK, TICKER);
+        let quote = BitstampProvider::new(test_util::http_client(), &url).quote("usd").await.unwrap();
+        assert_eq!(quote.price, 65000.5);
+        assert_eq!(quote.volume, Some(200.0 * 65000.5));
+        assert_eq!(quote.high, Some(66000.0));
+        assert_eq!(quote.low, Some(64000.0));
+        assert_eq!(quote.market_cap, None);
+        assert_eq!(*requests.lock().unwrap(), vec!["/api/v2/ticker/btcusd/"]);
+    }
+}
@@ -0,0 +1,62 @@
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use crate::http::HttpClient;
+use super::{parse_number, PriceProvider, PriceQuote, PriceResult};
+
+// Coinbase Exchange 24h stats for BTC against the requested currency (BTC-USD, BTC-EUR, ...).
+pub struct CoinbaseProvider {
+    http: Arc<HttpClient>,
+    base_url: String,
+}
+
+impl CoinbaseProvider {
+    pub fn new(http: Arc<HttpClient>, base_url: &str) -> Self {
+        CoinbaseProvider {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl PriceProvider for CoinbaseProvider {
+    fn name(&self) -> &'static str {
+        "coinbase"
+    }
+
+    async fn quote(&self, currency: &str) -> PriceResult<PriceQuote> {
+        let stats_url = format!("{}/products/BTC-{}/stats", self.base_url, currency.to_uppercase());
+        let stats = self.http.get_json::<serde_json::Value>(&stats_url).await?;
+
+        let price = parse_number(&stats["last"]).ok_or("Coinbase stats have no last price")?;
+        Ok(PriceQuote {
+            price,
+            volume: parse_number(&stats["volume"]).map(|volume| volume * price),
+            high: parse_number(&stats["high"]),
+            low: parse_number(&stats["low"]),
+            market_cap: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use warp::http::StatusCode;
+
+    const TICKER: &str = r#"{"open":"64800.0","high":"66000.0","low":"64000.0","last":"65000.5","volume":"200.0"}"#;
+
+    #[tokio::test]
+    async fn quotes_the_requested_currency() {
+        let (url, requests) = test_util::serve_response(StatusCode::OK, TICKER);
+        let quote = CoinbaseProvider::new(test_util::http_client(), &url).quote("usd").await.unwrap();
+        assert_eq!(quote.price, 65000.5);
+        assert_eq!(quote.volume, Some(200.0 * 65000.5));
+        assert_eq!(quote.high, Some(66000.0));
+        assert_eq!(quote.low, Some(64000.0));
+        assert_eq!(quote.market_cap, None);
+        assert_eq!(*requests.lock().unwrap(), vec!["/products/BTC-USD/stats"]);
+    }
+}
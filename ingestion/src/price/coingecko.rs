@@ -0,0 +1,110 @@
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use crate::http::HttpClient;
+use super::{PriceHistory, PriceProvider, PriceQuote, PriceResult};
+
+// CoinGecko's market_chart for the last day: the latest price, market cap and total volume,
+// with the day's high and low taken from the price series.
+pub struct CoinGeckoProvider {
+    http: Arc<HttpClient>,
+    base_url: String,
+}
+
+impl CoinGeckoProvider {
+    pub fn new(http: Arc<HttpClient>, base_url: &str) -> Self {
+        CoinGeckoProvider {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl PriceProvider for CoinGeckoProvider {
+    fn name(&self) -> &'static str {
+        "coingecko"
+    }
+
+    async fn quote(&self, currency: &str) -> PriceResult<PriceQuote> {
+        let chart_url = format!("{}/coins/bitcoin/market_chart", self.base_url);
+        let chart = self.http
+            .get_json_with_query::<serde_json::Value>(&chart_url, &[("vs_currency", currency), ("days", "1")])
+            .await?;
+
+        let series = |key: &str| -> Vec<f64> {
+            chart[key].as_array()
+                .map(|points| points.iter().filter_map(|point| point[1].as_f64()).collect())
+                .unwrap_or_default()
+        };
+        let prices = series("prices");
+
+        Ok(PriceQuote {
+            price: *prices.last().ok_or("CoinGecko returned no prices")?,
+            volume: series("total_volumes").last().copied(),
+            high: prices.iter().copied().reduce(f64::max),
+            low: prices.iter().copied().reduce(f64::min),
+            market_cap: series("market_caps").last().copied(),
+        })
+    }
+}
+
+// market_chart/range returns 5 minute points for ranges up to a day, hourly up to 90 days and
+// daily beyond that, so callers should ask for short ranges.
+#[async_trait]
+impl PriceHistory for CoinGeckoProvider {
+    fn name(&self) -> &'static str {
+        "coingecko"
+    }
+
+    async fn prices_between(&self, currency: &str, from: i64, to: i64) -> PriceResult<Vec<(i64, f64)>> {
+        let range_url = format!("{}/coins/bitcoin/market_chart/range", self.base_url);
+        let (from, to) = (from.to_string(), to.to_string());
+        let chart = self.http
+            .get_json_with_query::<serde_json::Value>(&range_url, &[("vs_currency", currency), ("from", &from), ("to", &to)])
+            .await?;
+
+        // Timestamps come back in milliseconds.
+        let mut points: Vec<(i64, f64)> = chart["prices"].as_array()
+            .map(|points| points.iter().filter_map(|point| Some((point[0].as_i64()? / 1000, point[1].as_f64()?))).collect())
+            .unwrap_or_default();
+        points.sort_by_key(|(time, _)| *time);
+        Ok(points)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use warp::http::StatusCode;
+
+    const TICKER: &str = r#"{"prices":[[1700000000000,64000.0],[1700000300000,66000.0],[1700000600000,65000.5]],"market_caps":[[1700000600000,1.28e12]],"total_volumes":[[1700000600000,3.0e10]]}"#;
+
+    #[tokio::test]
+    async fn quotes_the_requested_currency() {
+        let (url, requests) = test_util::serve_response(StatusCode::OK, TICKER);
+        let quote = CoinGeckoProvider::new(test_util::http_client(), &url).quote("usd").await.unwrap();
+        assert_eq!(quote.price, 65000.5);
+        assert_eq!(quote.volume, Some(3.0e10));
+        assert_eq!(quote.high, Some(66000.0));
+        assert_eq!(quote.low, Some(64000.0));
+        assert_eq!(quote.market_cap, Some(1.28e12));
+        assert_eq!(*requests.lock().unwrap(), vec!["/coins/bitcoin/market_chart?vs_currency=usd&days=1"]);
+    }
+
+    #[tokio::test]
+    async fn reads_price_history_in_seconds_oldest_first() {
+        let body = r#"{"prices":[[1700000600000,65000.5],[1700000000000,64000.0]]}"#;
+        let (url, requests) = test_util::serve_response(StatusCode::OK, body);
+        let points = CoinGeckoProvider::new(test_util::http_client(), &url)
+            .prices_between("usd", 1_699_999_000, 1_700_001_000)
+            .await
+            .unwrap();
+        assert_eq!(points, vec![(1_700_000_000, 64000.0), (1_700_000_600, 65000.5)]);
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec!["/coins/bitcoin/market_chart/range?vs_currency=usd&from=1699999000&to=1700001000"]
+        );
+    }
+}
@@ -0,0 +1,70 @@
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate};
+use std::collections::HashMap;
+use std::fs;
+use tracing::info;
+
+use super::{PriceHistory, PriceResult};
+
+// Price history from a local CSV file with one `time,price[,currency]` row per line. Time is
+// either unix seconds, an RFC 3339 timestamp or a YYYY-MM-DD date (taken as midnight UTC); rows
+// without a currency are USD. A header line and any further columns are ignored.
+pub struct CsvPriceHistory {
+    points: HashMap<String, Vec<(i64, f64)>>,
+}
+
+fn parse_time(text: &str) -> Option<i64> {
+    if let Ok(seconds) = text.parse::<i64>() {
+        return Some(seconds);
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time.timestamp());
+    }
+    NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|time| time.and_utc().timestamp())
+}
+
+impl CsvPriceHistory {
+    pub fn load(path: &str) -> PriceResult<Self> {
+        let contents = fs::read_to_string(path)?;
+        let mut points: HashMap<String, Vec<(i64, f64)>> = HashMap::new();
+
+        for (index, line) in contents.lines().enumerate() {
+            let mut columns = line.split(',').map(|column| column.trim().trim_matches('"'));
+            let (Some(time), Some(price)) = (columns.next(), columns.next()) else {
+                continue;
+            };
+            let currency = columns.next().filter(|currency| !currency.is_empty()).unwrap_or("usd").to_lowercase();
+            match (parse_time(time), price.parse::<f64>()) {
+                (Some(time), Ok(price)) => points.entry(currency).or_default().push((time, price)),
+                _ if index == 0 => continue,
+                _ => return Err(format!("{}:{}: cannot parse price row", path, index + 1).into()),
+            }
+        }
+
+        for series in points.values_mut() {
+            series.sort_by_key(|(time, _)| *time);
+        }
+        info!(points = points.values().map(Vec::len).sum::<usize>(), path, "Loaded price history");
+        Ok(CsvPriceHistory { points })
+    }
+}
+
+#[async_trait]
+impl PriceHistory for CsvPriceHistory {
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+
+    async fn prices_between(&self, currency: &str, from: i64, to: i64) -> PriceResult<Vec<(i64, f64)>> {
+        let Some(points) = self.points.get(currency) else {
+            return Ok(Vec::new());
+        };
+        // Keep one point either side of the range so edge times can still be interpolated.
+        let start = points.partition_point(|(time, _)| *time < from).saturating_sub(1);
+        let end = (points.partition_point(|(time, _)| *time <= to) + 1).min(points.len());
+        Ok(points[start..end].to_vec())
+    }
+}
@@ -0,0 +1,80 @@
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use crate::http::HttpClient;
+use super::{parse_number, PriceProvider, PriceQuote, PriceResult};
+
+// Kraken's public Ticker for XBT against the requested currency (XBTUSD, XBTEUR, ...). Values
+// are strings; the second element of each pair is the rolling 24h figure.
+pub struct KrakenProvider {
+    http: Arc<HttpClient>,
+    base_url: String,
+}
+
+impl KrakenProvider {
+    pub fn new(http: Arc<HttpClient>, base_url: &str) -> Self {
+        KrakenProvider {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl PriceProvider for KrakenProvider {
+    fn name(&self) -> &'static str {
+        "kraken"
+    }
+
+    async fn quote(&self, currency: &str) -> PriceResult<PriceQuote> {
+        let ticker_url = format!("{}/0/public/Ticker", self.base_url);
+        let pair = format!("XBT{}", currency.to_uppercase());
+        let response = self.http.get_json_with_query::<serde_json::Value>(&ticker_url, &[("pair", &pair)]).await?;
+
+        if let Some(error) = response["error"].as_array().and_then(|errors| errors.first()) {
+            return Err(format!("Kraken returned an error: {}", error).into());
+        }
+        // The result is keyed by Kraken's own pair name (XXBTZUSD, XXBTZEUR), so take whichever
+        // entry is there.
+        let ticker = response["result"].as_object()
+            .and_then(|result| result.values().next())
+            .ok_or("Kraken returned no ticker")?;
+
+        let price = parse_number(&ticker["c"][0]).ok_or("Kraken ticker has no last price")?;
+        Ok(PriceQuote {
+            price,
+            volume: parse_number(&ticker["v"][1]).map(|volume| volume * price),
+            high: parse_number(&ticker["h"][1]),
+            low: parse_number(&ticker["l"][1]),
+            market_cap: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use warp::http::StatusCode;
+
+    const TICKER: &str = r#"{"error":[],"result":{"XXBTZUSD":{"c":["65000.5","0.01"],"v":["12.0","200.0"],"h":["65500.0","66000.0"],"l":["64500.0","64000.0"]}}}"#;
+
+    #[tokio::test]
+    async fn quotes_the_requested_currency() {
+        let (url, requests) = test_util::serve_response(StatusCode::OK, TICKER);
+        let quote = KrakenProvider::new(test_util::http_client(), &url).quote("usd").await.unwrap();
+        assert_eq!(quote.price, 65000.5);
+        assert_eq!(quote.volume, Some(200.0 * 65000.5));
+        assert_eq!(quote.high, Some(66000.0));
+        assert_eq!(quote.low, Some(64000.0));
+        assert_eq!(quote.market_cap, None);
+        assert_eq!(*requests.lock().unwrap(), vec!["/0/public/Ticker?pair=XBTUSD"]);
+    }
+
+    #[tokio::test]
+    async fn fails_on_an_error_body() {
+        let (url, _) = test_util::serve_response(StatusCode::OK, r#"{"error":["EQuery:Unknown asset pair"]}"#);
+        let error = KrakenProvider::new(test_util::http_client(), &url).quote("usd").await.unwrap_err();
+        assert!(error.to_string().contains("EQuery:Unknown asset pair"));
+    }
+}
@@ -0,0 +1,334 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use diesel::prelude::*;
+use std::error::Error;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+use tracing::{error, warn, Instrument};
+
+use crate::config::{OffchainConfig, PriceHistoryConfig};
+use crate::http::HttpClient;
+use crate::metrics;
+use crate::schema::price_quotes;
+
+mod bitstamp;
+mod coinbase;
+mod coingecko;
+mod csv;
+mod kraken;
+pub use bitstamp::BitstampProvider;
+pub use coinbase::CoinbaseProvider;
+pub use coingecko::CoinGeckoProvider;
+pub use csv::CsvPriceHistory;
+pub use kraken::KrakenProvider;
+
+pub type PriceResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+// A BTC price feed. Currencies are lowercase ISO codes such as "usd" or "eur"; providers
+// that do not quote a currency return an error for it.
+#[async_trait]
+pub trait PriceProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn quote(&self, currency: &str) -> PriceResult<PriceQuote>;
+}
+
+// Past BTC prices, used to price blocks at their own timestamp.
+#[async_trait]
+pub trait PriceHistory: Send + Sync {
+    fn name(&self) -> &'static str;
+    // (unix time, price) points covering from..=to, oldest first.
+    async fn prices_between(&self, currency: &str, from: i64, to: i64) -> PriceResult<Vec<(i64, f64)>>;
+}
+
+// One provider's view of the market, in the quoted currency. Volumes are 24h; market cap is
+// only known to aggregators such as CoinGecko.
+#[derive(Debug, Clone)]
+pub struct PriceQuote {
+    pub price: f64,
+    pub volume: Option<f64>,
+    pub high: Option<f64>,
+    pub low: Option<f64>,
+    pub market_cap: Option<f64>,
+}
+
+pub struct AggregatedPrice {
+    pub price: f64,
+    pub volume: Option<f64>,
+    pub high: Option<f64>,
+    pub low: Option<f64>,
+    pub market_cap: Option<f64>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = price_quotes)]
+struct NewPriceQuote {
+    block_height: i32,
+    currency: String,
+    provider: String,
+    price: f64,
+    volume: Option<f64>,
+    high: Option<f64>,
+    low: Option<f64>,
+    market_cap: Option<f64>,
+    accepted: bool,
+    fetched_at: chrono::NaiveDateTime,
+}
+
+pub(crate) fn parse_number(value: &serde_json::Value) -> Option<f64> {
+    match value {
+        serde_json::Value::String(text) => text.parse().ok(),
+        other => other.as_f64(),
+    }
+}
+
+// Builds the configured providers, each against its configured base URL.
+pub fn providers(config: &OffchainConfig, http: &Arc<HttpClient>) -> Vec<Arc<dyn PriceProvider>> {
+    config
+        .providers
+        .iter()
+        .map(|name| -> Arc<dyn PriceProvider> {
+            match name.as_str() {
+                "coingecko" => Arc::new(CoinGeckoProvider::new(Arc::clone(http), &config.coingecko_url)),
+                "kraken" => Arc::new(KrakenProvider::new(Arc::clone(http), &config.kraken_url)),
+                "coinbase" => Arc::new(CoinbaseProvider::new(Arc::clone(http), &config.coinbase_url)),
+                "bitstamp" => Arc::new(BitstampProvider::new(Arc::clone(http), &config.bitstamp_url)),
+                other => panic!("Unknown price provider: {}", other),
+            }
+        })
+        .collect()
+}
+
+// Where block prices come from: CoinGecko's market_chart/range, or a CSV file loaded once at
+// startup.
+pub fn history(config: &OffchainConfig, http: &Arc<HttpClient>) -> PriceResult<Arc<dyn PriceHistory>> {
+    Ok(match &config.history {
+        PriceHistoryConfig::CoinGecko => Arc::new(CoinGeckoProvider::new(Arc::clone(http), &config.coingecko_url)),
+        PriceHistoryConfig::Csv(path) => Arc::new(
+            CsvPriceHistory::load(path).map_err(|e| format!("could not load the price history from {}: {}", path, e))?,
+        ),
+    })
+}
+
+// Price at `time`, interpolated between the surrounding points. Outside the series the nearest
+// point is used if it is no more than `max_gap` seconds away.
+pub fn price_at(points: &[(i64, f64)], time: i64, max_gap: i64) -> Option<f64> {
+    let after = points.partition_point(|(point_time, _)| *point_time < time);
+    match (after.checked_sub(1).map(|i| points[i]), points.get(after).copied()) {
+        (Some((t0, p0)), Some((t1, p1))) if t1 > t0 => Some(p0 + (p1 - p0) * (time - t0) as f64 / (t1 - t0) as f64),
+        (_, Some((t1, p1))) if t1 - time <= max_gap => Some(p1),
+        (Some((t0, p0)), None) if time - t0 <= max_gap => Some(p0),
+        _ => None,
+    }
+}
+
+// Asks every provider at once. Failures are logged and left out.
+pub async fn fetch_quotes(providers: &[Arc<dyn PriceProvider>], currency: &str) -> Vec<(&'static str, PriceQuote)> {
+    let mut requests = JoinSet::new();
+    for provider in providers {
+        let provider = Arc::clone(provider);
+        let currency = currency.to_string();
+        requests.spawn(async move { (provider.name(), provider.quote(&currency).await) }.in_current_span());
+    }
+
+    let mut quotes = Vec::new();
+    while let Some(joined) = requests.join_next().await {
+        match joined {
+            Ok((name, Ok(quote))) if quote.price.is_finite() && quote.price > 0.0 => {
+                metrics::PRICE_FETCHES.with_label_values(&[name, "success"]).inc();
+                quotes.push((name, quote));
+            }
+            Ok((name, Ok(quote))) => {
+                metrics::PRICE_FETCHES.with_label_values(&[name, "error"]).inc();
+                warn!(provider = name, price = quote.price, "Ignoring quote with an invalid price");
+            }
+            Ok((name, Err(e))) => {
+                metrics::PRICE_FETCHES.with_label_values(&[name, "error"]).inc();
+                error!(provider = name, currency, error = %e, "Error fetching price");
+            }
+            Err(e) => error!(error = %e, "Price provider task failed"),
+        }
+    }
+    quotes.sort_by_key(|(name, _)| *name);
+    quotes
+}
+
+// Middle value, or the mean of the two middle values for an even count.
+pub fn median(mut values: Vec<f64>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    Some(if values.len().is_multiple_of(2) { (values[mid - 1] + values[mid]) / 2.0 } else { values[mid] })
+}
+
+// Marks which quotes are within `max_deviation` (a fraction, e.g. 0.02) of the median price.
+// With two quotes there is no majority to side with: both are kept if they agree and both are
+// rejected if they do not, since either could be the wrong one.
+pub fn accepted_quotes(quotes: &[(&'static str, PriceQuote)], max_deviation: f64) -> Vec<bool> {
+    let Some(center) = median(quotes.iter().map(|(_, quote)| quote.price).collect()) else {
+        return Vec::new();
+    };
+    if let [(_, a), (_, b)] = quotes {
+        return vec![((a.price - b.price) / center).abs() <= max_deviation; 2];
+    }
+    quotes
+        .iter()
+        .map(|(_, quote)| ((quote.price - center) / center).abs() <= max_deviation)
+        .collect()
+}
+
+// Median of every field over the accepted quotes.
+pub fn aggregate(quotes: &[(&'static str, PriceQuote)], accepted: &[bool]) -> Option<AggregatedPrice> {
+    let kept: Vec<&PriceQuote> = quotes
+        .iter()
+        .zip(accepted)
+        .filter(|(_, accepted)| **accepted)
+        .map(|((_, quote), _)| quote)
+        .collect();
+    let field = |get: fn(&PriceQuote) -> Option<f64>| median(kept.iter().filter_map(|quote| get(quote)).collect());
+
+    Some(AggregatedPrice {
+        price: field(|quote| Some(quote.price))?,
+        volume: field(|quote| quote.volume),
+        high: field(|quote| quote.high),
+        low: field(|quote| quote.low),
+        market_cap: field(|quote| quote.market_cap),
+    })
+}
+
+// Keeps every quote, accepted or not, so the aggregated price can be audited later.
+pub fn store_quotes(
+    conn: &mut PgConnection,
+    block_height: i32,
+    currency: &str,
+    quotes: &[(&'static str, PriceQuote)],
+    accepted: &[bool],
+) -> QueryResult<()> {
+    if quotes.is_empty() {
+        return Ok(());
+    }
+
+    let fetched_at = Utc::now().naive_utc();
+    let rows: Vec<NewPriceQuote> = quotes
+        .iter()
+        .zip(accepted)
+        .map(|((name, quote), accepted)| NewPriceQuote {
+            block_height,
+            currency: currency.to_string(),
+            provider: name.to_string(),
+            price: quote.price,
+            volume: quote.volume,
+            high: quote.high,
+            low: quote.low,
+            market_cap: quote.market_cap,
+            accepted: *accepted,
+            fetched_at,
+        })
+        .collect();
+
+    diesel::insert_into(price_quotes::table).values(&rows).execute(conn)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use warp::http::StatusCode;
+
+    fn quote(price: f64, volume: Option<f64>) -> PriceQuote {
+        PriceQuote { price, volume, high: None, low: None, market_cap: None }
+    }
+
+    fn quotes(prices: &[f64]) -> Vec<(&'static str, PriceQuote)> {
+        prices.iter().map(|&price| ("test", quote(price, None))).collect()
+    }
+
+    #[test]
+    fn median_of_odd_and_even_counts() {
+        assert_eq!(median(vec![]), None);
+        assert_eq!(median(vec![3.0]), Some(3.0));
+        assert_eq!(median(vec![5.0, 1.0, 3.0]), Some(3.0));
+        assert_eq!(median(vec![4.0, 1.0, 3.0, 2.0]), Some(2.5));
+    }
+
+    #[test]
+    fn accepts_quotes_near_the_median() {
+        // (prices, accepted at a 2% max deviation)
+        let cases: &[(&[f64], &[bool])] = &[
+            (&[], &[]),
+            (&[100.0], &[true]),
+            // Two quotes are kept only while they agree with each other.
+            (&[100.0, 101.5], &[true, true]),
+            (&[100.0, 110.0], &[false, false]),
+            (&[100.0, 101.0, 120.0], &[true, true, false]),
+            (&[100.0, 98.5, 101.0, 80.0], &[true, true, true, false]),
+            (&[100.0, 150.0, 50.0], &[true, false, false]),
+        ];
+        for (prices, expected) in cases {
+            assert_eq!(accepted_quotes(&quotes(prices), 0.02), *expected, "prices {:?}", prices);
+        }
+    }
+
+    #[test]
+    fn aggregates_the_accepted_quotes() {
+        let quotes = vec![
+            ("a", quote(100.0, Some(10.0))),
+            ("b", quote(102.0, None)),
+            ("c", quote(101.0, Some(30.0))),
+            ("d", quote(500.0, Some(1_000.0))),
+        ];
+        let aggregated = aggregate(&quotes, &[true, true, true, false]).unwrap();
+        assert_eq!(aggregated.price, 101.0);
+        assert_eq!(aggregated.volume, Some(20.0));
+        assert_eq!(aggregated.high, None);
+        assert_eq!(aggregated.market_cap, None);
+
+        assert!(aggregate(&quotes, &[false; 4]).is_none());
+        assert!(aggregate(&[], &[]).is_none());
+    }
+
+    #[test]
+    fn prices_between_and_near_points() {
+        let points = [(1_000, 100.0), (2_000, 200.0), (4_000, 300.0)];
+        // (time, expected price with a 500 second max gap)
+        let cases = [
+            // Before the first point: the first price if close enough.
+            (400, None),
+            (500, Some(100.0)),
+            // Exactly on a point.
+            (1_000, Some(100.0)),
+            (2_000, Some(200.0)),
+            (4_000, Some(300.0)),
+            // Between points, interpolated however far apart they are.
+            (1_500, Some(150.0)),
+            (3_000, Some(250.0)),
+            // After the last point: the last price if close enough.
+            (4_500, Some(300.0)),
+            (4_501, None),
+        ];
+        for (time, expected) in cases {
+            assert_eq!(price_at(&points, time, 500), expected, "time {}", time);
+        }
+        assert_eq!(price_at(&[], 1_000, 500), None);
+    }
+
+    #[tokio::test]
+    async fn providers_fail_on_a_malformed_body() {
+        type NewProvider = fn(Arc<HttpClient>, &str) -> Arc<dyn PriceProvider>;
+        let providers: [(&str, NewProvider); 4] = [
+            ("coingecko", |http, url| Arc::new(CoinGeckoProvider::new(http, url))),
+            ("coinbase", |http, url| Arc::new(CoinbaseProvider::new(http, url))),
+            ("kraken", |http, url| Arc::new(KrakenProvider::new(http, url))),
+            ("bitstamp", |http, url| Arc::new(BitstampProvider::new(http, url))),
+        ];
+        for (name, provider) in providers {
+            for body in ["<html>Bad gateway</html>", "{}", "[]"] {
+                let (url, _) = test_util::serve_response(StatusCode::OK, body);
+                let result = provider(test_util::http_client(), &url).quote("usd").await;
+                assert!(result.is_err(), "{} accepted {}", name, body);
+            }
+        }
+    }
+}
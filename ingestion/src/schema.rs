@@ -1,5 +1,27 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    addresses (address) {
+        address -> Varchar,
+        funded_txo_count -> Int8,
+        funded_txo_sum -> Int8,
+        spent_txo_count -> Int8,
+        spent_txo_sum -> Int8,
+        tx_count -> Int8,
+        first_seen_height -> Int4,
+        last_seen_height -> Int4,
+    }
+}
+
+diesel::table! {
+    backfill_progress (start_height) {
+        start_height -> Int4,
+        end_height -> Int4,
+        next_height -> Int4,
+        updated_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     block_height (id) {
         id -> Int4,
@@ -14,6 +36,16 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    block_prices (height, currency) {
+        height -> Int4,
+        btc_price -> Nullable<Float8>,
+        source -> Varchar,
+        priced_at -> Timestamp,
+        currency -> Varchar,
+    }
+}
+
 diesel::table! {
     block_info (id) {
         id -> Int4,
@@ -24,6 +56,20 @@ diesel::table! {
         timestamp -> Timestamp,
         size -> Int4,
         weight -> Int4,
+        hash -> Nullable<Varchar>,
+        previous_hash -> Nullable<Varchar>,
+        complete -> Bool,
+    }
+}
+
+diesel::table! {
+    mempool_transactions (txid) {
+        txid -> Varchar,
+        fee -> Int8,
+        vsize -> Int8,
+        weight -> Int8,
+        first_seen -> Timestamp,
+        confirmed_height -> Nullable<Int4>,
     }
 }
 
@@ -37,6 +83,23 @@ diesel::table! {
         high -> Nullable<Float8>,
         low -> Nullable<Float8>,
         timestamp -> Timestamp,
+        currency -> Varchar,
+    }
+}
+
+diesel::table! {
+    price_quotes (id) {
+        id -> Int4,
+        block_height -> Int4,
+        provider -> Varchar,
+        price -> Float8,
+        volume -> Nullable<Float8>,
+        high -> Nullable<Float8>,
+        low -> Nullable<Float8>,
+        market_cap -> Nullable<Float8>,
+        accepted -> Bool,
+        fetched_at -> Timestamp,
+        currency -> Varchar,
     }
 }
 
@@ -45,7 +108,13 @@ diesel::table! {
         id -> Int4,
         transaction_id -> Int4,
         previous_output -> Varchar,
-        value -> Int8,
+        value -> Nullable<Int8>,
+        vin -> Int4,
+        previous_vout -> Nullable<Int4>,
+        sequence -> Int8,
+        scriptsig -> Varchar,
+        witness -> Array<Text>,
+        is_coinbase -> Bool,
     }
 }
 
@@ -55,6 +124,10 @@ diesel::table! {
         transaction_id -> Int4,
         address -> Varchar,
         value -> Int8,
+        vout -> Int4,
+        script_hex -> Varchar,
+        script_asm -> Varchar,
+        script_type -> Varchar,
     }
 }
 
@@ -64,8 +137,21 @@ diesel::table! {
         block_height -> Int4,
         hash -> Varchar,
         btc -> Float8,
-        fee -> Int8,
+        fee -> Nullable<Int8>,
         time -> Int8,
+        vsize -> Int4,
+        weight -> Int4,
+    }
+}
+
+diesel::table! {
+    utxos (txid, vout) {
+        txid -> Varchar,
+        vout -> Int4,
+        value -> Int8,
+        script_type -> Varchar,
+        address -> Varchar,
+        height -> Int4,
     }
 }
 
@@ -73,11 +159,17 @@ diesel::joinable!(transaction_inputs -> transactions (transaction_id));
 diesel::joinable!(transaction_outputs -> transactions (transaction_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    addresses,
+    backfill_progress,
     block_height,
     block_heights,
     block_info,
+    block_prices,
+    mempool_transactions,
     offchain_data,
+    price_quotes,
     transaction_inputs,
     transaction_outputs,
     transactions,
+    utxos,
 );
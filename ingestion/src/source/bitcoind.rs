@@ -0,0 +1,569 @@
+use async_trait::async_trait;
+use bitcoin::ScriptBuf;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::sync::Arc;
+
+use crate::http::HttpClient;
+use super::{script_type, ApiBlockInfo, ApiTransaction, ApiTransactionInput, ApiTransactionOutput, BlockSource, MempoolEntry, MempoolSource, SourceResult};
+
+const COINBASE_PREV_TXID: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+// What getmempoolentry fails with for a txid that is not (or no longer) in the mempool.
+const RPC_INVALID_ADDRESS_OR_KEY: i64 = -5;
+
+#[derive(Clone)]
+pub enum RpcAuth {
+    UserPass(String, String),
+    // Path to the .cookie file bitcoind writes into its datadir; re-read on every call since
+    // the node rotates it on restart.
+    CookieFile(String),
+}
+
+// Bitcoin Core JSON-RPC client using getblockcount, getblockhash and getblock. Input values come
+// from getblock's verbosity 3 (Bitcoin Core 25 and later); older nodes answer that like
+// verbosity 2, without prevouts, and need -txindex so they can be looked up with
+// getrawtransaction.
+pub struct BitcoindSource {
+    http: Arc<HttpClient>,
+    url: String,
+    auth: RpcAuth,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error: Option<RpcError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+    #[serde(skip)]
+    method: String,
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed with RPC error {}: {}", self.method, self.code, self.message)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+#[derive(Deserialize)]
+struct RpcBlock {
+    hash: String,
+    height: i32,
+    time: i64,
+    #[serde(rename = "nTx")]
+    n_tx: i32,
+    size: i32,
+    weight: i32,
+    previousblockhash: Option<String>,
+    mediantime: i64,
+    difficulty: f64,
+}
+
+#[derive(Deserialize)]
+struct RpcBlockWithTxs {
+    tx: Vec<RpcTransaction>,
+}
+
+#[derive(Deserialize)]
+struct RpcTransaction {
+    txid: String,
+    // BTC; absent for coinbase transactions and when the node has no undo data for the block.
+    fee: Option<f64>,
+    weight: i32,
+    vin: Vec<RpcInput>,
+    vout: Vec<RpcOutput>,
+}
+
+#[derive(Deserialize)]
+struct RpcInput {
+    txid: Option<String>,
+    vout: Option<u32>,
+    // Set instead of txid/vout/scriptSig on a coinbase input.
+    coinbase: Option<String>,
+    #[serde(rename = "scriptSig")]
+    script_sig: Option<RpcScriptSig>,
+    txinwitness: Option<Vec<String>>,
+    sequence: i64,
+    // Only with verbosity 3, and only when the node has undo data for the block.
+    prevout: Option<RpcOutput>,
+}
+
+#[derive(Deserialize)]
+struct RpcScriptSig {
+    hex: String,
+}
+
+#[derive(Deserialize)]
+struct RpcRawTransaction {
+    vout: Vec<RpcOutput>,
+}
+
+#[derive(Deserialize, Clone)]
+struct RpcOutput {
+    value: f64,
+    #[serde(rename = "scriptPubKey")]
+    script_pub_key: RpcScriptPubKey,
+}
+
+#[derive(Deserialize, Clone)]
+struct RpcScriptPubKey {
+    asm: String,
+    hex: String,
+    address: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RpcMempoolEntry {
+    vsize: i64,
+    weight: i64,
+    fees: RpcMempoolFees,
+}
+
+#[derive(Deserialize)]
+struct RpcMempoolFees {
+    base: f64,
+}
+
+fn btc_to_sats(btc: f64) -> i64 {
+    (btc * 100_000_000.0).round() as i64
+}
+
+fn api_output(output: RpcOutput) -> ApiTransactionOutput {
+    let script = ScriptBuf::from_hex(&output.script_pub_key.hex).unwrap_or_default();
+    ApiTransactionOutput {
+        scriptpubkey_type: script_type(&script).to_string(),
+        scriptpubkey: output.script_pub_key.hex,
+        scriptpubkey_asm: output.script_pub_key.asm,
+        scriptpubkey_address: output.script_pub_key.address,
+        value: btc_to_sats(output.value),
+    }
+}
+
+impl BitcoindSource {
+    pub fn new(http: Arc<HttpClient>, url: &str, auth: RpcAuth) -> Self {
+        BitcoindSource {
+            http,
+            url: url.to_string(),
+            auth,
+        }
+    }
+
+    async fn call<T: DeserializeOwned>(&self, method: &str, params: serde_json::Value) -> SourceResult<T> {
+        let (user, password) = match &self.auth {
+            RpcAuth::UserPass(user, password) => (user.clone(), password.clone()),
+            RpcAuth::CookieFile(path) => {
+                let cookie = fs::read_to_string(path)?;
+                let (user, password) = cookie
+                    .trim()
+                    .split_once(':')
+                    .ok_or_else(|| format!("Malformed cookie file: {}", path))?;
+                (user.to_string(), password.to_string())
+            }
+        };
+
+        let body = json!({
+            "jsonrpc": "1.0",
+            "id": "ingestion",
+            "method": method,
+            "params": params,
+        });
+
+        // bitcoind answers RPC errors with HTTP 500 and a JSON body, so read the body before
+        // looking at the status.
+        let response = self.http.send(&self.url, |client| {
+            client.post(&self.url)
+                .basic_auth(&user, Some(&password))
+                .json(&body)
+        }).await?;
+        let status = response.status();
+        let text = response.text().await?;
+
+        let rpc_response = match serde_json::from_str::<RpcResponse<T>>(&text) {
+            Ok(rpc_response) => rpc_response,
+            Err(_) if !status.is_success() => return Err(format!("{} returned HTTP {}", method, status).into()),
+            Err(e) => return Err(e.into()),
+        };
+
+        if let Some(mut error) = rpc_response.error {
+            error.method = method.to_string();
+            return Err(error.into());
+        }
+        rpc_response.result.ok_or_else(|| format!("{} returned no result", method).into())
+    }
+}
+
+#[async_trait]
+impl BlockSource for BitcoindSource {
+    async fn tip_height(&self) -> SourceResult<i32> {
+        self.call("getblockcount", json!([])).await
+    }
+
+    async fn block_hash(&self, height: i32) -> SourceResult<String> {
+        self.call("getblockhash", json!([height])).await
+    }
+
+    async fn block(&self, hash: &str) -> SourceResult<ApiBlockInfo> {
+        let block: RpcBlock = self.call("getblock", json!([hash, 1])).await?;
+        Ok(ApiBlockInfo {
+            id: block.hash,
+            height: block.height,
+            timestamp: block.time,
+            tx_count: block.n_tx,
+            size: block.size,
+            weight: block.weight,
+            previousblockhash: block.previousblockhash,
+            mediantime: block.mediantime,
+            difficulty: block.difficulty,
+        })
+    }
+
+    async fn block_txs(&self, block: &ApiBlockInfo) -> SourceResult<Vec<ApiTransaction>> {
+        let hash = &block.id;
+        let block: RpcBlockWithTxs = self.call("getblock", json!([hash, 3])).await?;
+
+        // Outputs of the transactions whose outputs are spent here without a prevout attached.
+        let mut missing: HashSet<&str> = HashSet::new();
+        for vin in block.tx.iter().flat_map(|tx| &tx.vin) {
+            if let (None, None, Some(txid)) = (&vin.coinbase, &vin.prevout, &vin.txid) {
+                missing.insert(txid);
+            }
+        }
+        let mut spent_txs: HashMap<String, Vec<RpcOutput>> = HashMap::new();
+        for txid in missing {
+            let tx: RpcRawTransaction = self.call("getrawtransaction", json!([txid, true])).await.map_err(|e| {
+                format!("Could not look up the outputs spent from {} (needs Bitcoin Core 25+ or -txindex): {}", txid, e)
+            })?;
+            spent_txs.insert(txid.to_string(), tx.vout);
+        }
+
+        let mut txs = Vec::with_capacity(block.tx.len());
+        for tx in block.tx {
+            let mut vin = Vec::with_capacity(tx.vin.len());
+            for input in tx.vin {
+                let prevout = match (&input.coinbase, input.prevout, &input.txid, input.vout) {
+                    (Some(_), _, _, _) => None,
+                    (None, Some(prevout), _, _) => Some(prevout),
+                    (None, None, Some(txid), Some(vout)) => Some(
+                        spent_txs
+                            .get(txid)
+                            .and_then(|outputs| outputs.get(vout as usize))
+                            .cloned()
+                            .ok_or_else(|| format!("Output {}:{} spent in block {} does not exist", txid, vout, hash))?,
+                    ),
+                    (None, None, _, _) => return Err(format!("Input without an outpoint in block {}", hash).into()),
+                };
+                vin.push(ApiTransactionInput {
+                    txid: input.txid.unwrap_or_else(|| COINBASE_PREV_TXID.to_string()),
+                    vout: input.vout.unwrap_or(u32::MAX),
+                    prevout: prevout.map(api_output),
+                    is_coinbase: input.coinbase.is_some(),
+                    scriptsig: input.coinbase.or(input.script_sig.map(|script_sig| script_sig.hex)).unwrap_or_default(),
+                    witness: input.txinwitness.unwrap_or_default(),
+                    sequence: input.sequence,
+                });
+            }
+            let vout: Vec<ApiTransactionOutput> = tx.vout.into_iter().map(api_output).collect();
+            // Without undo data the node leaves out the fee, but the prevouts give it all the same.
+            let fee = match tx.fee {
+                Some(fee) => btc_to_sats(fee),
+                None if vin.iter().any(|input| input.is_coinbase) => 0,
+                None => {
+                    vin.iter().filter_map(|input| input.prevout.as_ref()).map(|prevout| prevout.value).sum::<i64>()
+                        - vout.iter().map(|output| output.value).sum::<i64>()
+                }
+            };
+            txs.push(ApiTransaction {
+                txid: tx.txid,
+                fee: Some(fee),
+                weight: tx.weight,
+                vin,
+                vout,
+            });
+        }
+        Ok(txs)
+    }
+}
+
+#[async_trait]
+impl MempoolSource for BitcoindSource {
+    async fn mempool_txids(&self) -> SourceResult<Vec<String>> {
+        self.call("getrawmempool", json!([false])).await
+    }
+
+    // One getmempoolentry per txid rather than a verbose getrawmempool, which would serialize the
+    // whole mempool on every poll.
+    async fn mempool_entries(&self, txids: &[String]) -> SourceResult<Vec<MempoolEntry>> {
+        let mut entries = Vec::with_capacity(txids.len());
+        for txid in txids {
+            let entry: RpcMempoolEntry = match self.call("getmempoolentry", json!([txid])).await {
+                Ok(entry) => entry,
+                Err(e) if e.downcast_ref::<RpcError>().is_some_and(|e| e.code == RPC_INVALID_ADDRESS_OR_KEY) => continue,
+                Err(e) => return Err(e),
+            };
+            entries.push(MempoolEntry {
+                txid: txid.clone(),
+                fee: btc_to_sats(entry.fees.base),
+                vsize: entry.vsize,
+                weight: entry.weight,
+            });
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use serde_json::Value;
+    use warp::http::StatusCode;
+    use warp::Filter;
+
+    const BLOCK_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000065";
+    const MEMPOOL_TXID: &str = "dddddddddddddddddddddddddddddddddddddddddddddddddddddddddddddddd";
+    const SPENT_TXID: &str = "cccccccccccccccccccccccccccccccccccccccccccccccccccccccccccccccc";
+    // Basic auth for user:pass and for the cookie __cookie__:secret.
+    const USER_PASS_AUTH: &str = "Basic dXNlcjpwYXNz";
+    const COOKIE_AUTH: &str = "Basic X19jb29raWVfXzpzZWNyZXQ=";
+
+    fn p2wpkh() -> Value {
+        json!({
+            "asm": "0 751e76e8199196d454941c45d1b3a323f1433bd6",
+            "hex": "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+            "address": "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+            "type": "witness_v0_keyhash",
+        })
+    }
+
+    fn p2pkh() -> Value {
+        json!({
+            "asm": "OP_DUP OP_HASH160 62e907b15cbf27d5425399ebf6f0fb50ebb88f18 OP_EQUALVERIFY OP_CHECKSIG",
+            "hex": "76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac",
+            "address": "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa",
+            "type": "pubkeyhash",
+        })
+    }
+
+    // getblock at verbosity 3 from Bitcoin Core 25+, or without prevouts and fees as an older
+    // node without undo data answers it.
+    fn block_with_txs(with_prevouts: bool) -> Value {
+        let mut spend_input = json!({
+            "txid": SPENT_TXID,
+            "vout": 1,
+            "scriptSig": { "asm": "", "hex": "" },
+            "txinwitness": ["3044", "02aa"],
+            "sequence": 4294967293u32,
+        });
+        let mut spend = json!({
+            "txid": "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb",
+            "weight": 561,
+            "vout": [{ "value": 0.9999, "n": 0, "scriptPubKey": p2pkh() }],
+        });
+        if with_prevouts {
+            spend_input["prevout"] = json!({ "generated": false, "height": 50, "value": 1.0, "scriptPubKey": p2wpkh() });
+            spend["fee"] = json!(0.0001);
+        }
+        spend["vin"] = json!([spend_input]);
+
+        json!({
+            "hash": BLOCK_HASH,
+            "tx": [
+                {
+                    "txid": "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+                    "weight": 400,
+                    "vin": [{ "coinbase": "03650000", "txinwitness": ["00"], "sequence": 4294967295u32 }],
+                    "vout": [{ "value": 50.0001, "n": 0, "scriptPubKey": p2wpkh() }],
+                },
+                spend,
+            ],
+        })
+    }
+
+    // The same block's transactions as Esplora's /block/{hash}/txs returns them.
+    fn esplora_txs() -> Vec<ApiTransaction> {
+        let p2wpkh = json!({
+            "scriptpubkey": "0014751e76e8199196d454941c45d1b3a323f1433bd6",
+            "scriptpubkey_asm": "0 751e76e8199196d454941c45d1b3a323f1433bd6",
+            "scriptpubkey_type": "v0_p2wpkh",
+            "scriptpubkey_address": "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+        });
+        let mut coinbase_output = p2wpkh.clone();
+        coinbase_output["value"] = json!(5_000_010_000i64);
+        let mut prevout = p2wpkh;
+        prevout["value"] = json!(100_000_000);
+
+        serde_json::from_value(json!([
+            {
+                "txid": "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+                "fee": 0,
+                "weight": 400,
+                "vin": [{
+                    "txid": COINBASE_PREV_TXID,
+                    "vout": 4294967295u32,
+                    "prevout": null,
+                    "scriptsig": "03650000",
+                    "witness": ["00"],
+                    "is_coinbase": true,
+                    "sequence": 4294967295u32,
+                }],
+                "vout": [coinbase_output],
+            },
+            {
+                "txid": "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb",
+                "fee": 10_000,
+                "weight": 561,
+                "vin": [{
+                    "txid": SPENT_TXID,
+                    "vout": 1,
+                    "prevout": prevout,
+                    "scriptsig": "",
+                    "witness": ["3044", "02aa"],
+                    "is_coinbase": false,
+                    "sequence": 4294967293u32,
+                }],
+                "vout": [{
+                    "scriptpubkey": "76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac",
+                    "scriptpubkey_asm": "OP_DUP OP_HASH160 62e907b15cbf27d5425399ebf6f0fb50ebb88f18 OP_EQUALVERIFY OP_CHECKSIG",
+                    "scriptpubkey_type": "p2pkh",
+                    "scriptpubkey_address": "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa",
+                    "value": 99_990_000,
+                }],
+            },
+        ]))
+        .unwrap()
+    }
+
+    fn rpc_result(request: &Value, with_prevouts: bool) -> Value {
+        let params = &request["params"];
+        match (request["method"].as_str().unwrap_or_default(), params[1].as_i64()) {
+            ("getblockcount", _) => json!(101),
+            ("getblockhash", _) if params[0] == 101 => json!(BLOCK_HASH),
+            ("getblock", Some(1)) if params[0] == BLOCK_HASH => json!({
+                "hash": BLOCK_HASH,
+                "height": 101,
+                "time": 1_700_000_000,
+                "nTx": 2,
+                "size": 400,
+                "weight": 1_600,
+                "previousblockhash": "0000000000000000000000000000000000000000000000000000000000000064",
+                "mediantime": 1_699_999_000,
+                "difficulty": 1.0,
+            }),
+            ("getblock", Some(3)) if params[0] == BLOCK_HASH => block_with_txs(with_prevouts),
+            ("getrawtransaction", _) if params[0] == SPENT_TXID => json!({
+                "txid": SPENT_TXID,
+                "vout": [
+                    { "value": 2.0, "n": 0, "scriptPubKey": p2pkh() },
+                    { "value": 1.0, "n": 1, "scriptPubKey": p2wpkh() },
+                ],
+            }),
+            ("getmempoolentry", _) if params[0] == MEMPOOL_TXID => json!({
+                "vsize": 141,
+                "weight": 561,
+                "fees": { "base": 0.0000141 },
+            }),
+            _ => Value::Null,
+        }
+    }
+
+    // A node that only answers requests carrying `auth`, the way bitcoind does.
+    fn mock_node(auth: &'static str, with_prevouts: bool) -> String {
+        let route = warp::post()
+            .and(warp::header::<String>("authorization"))
+            .and(warp::body::json())
+            .map(move |header: String, request: Value| {
+                if header != auth {
+                    return warp::reply::with_status(warp::reply::json(&Value::Null), StatusCode::UNAUTHORIZED);
+                }
+                let result = rpc_result(&request, with_prevouts);
+                let (body, status) = if result.is_null() {
+                    let error = match request["method"].as_str() {
+                        Some("getmempoolentry") => json!({ "code": -5, "message": "Transaction not in mempool" }),
+                        _ => json!({ "code": -32601, "message": "Method not found" }),
+                    };
+                    (json!({ "result": null, "error": error, "id": request["id"] }), StatusCode::INTERNAL_SERVER_ERROR)
+                } else {
+                    (json!({ "result": result, "error": null, "id": request["id"] }), StatusCode::OK)
+                };
+                warp::reply::with_status(warp::reply::json(&body), status)
+            });
+        test_util::serve(route)
+    }
+
+    async fn assert_block(source: &BitcoindSource) {
+        assert_eq!(source.tip_height().await.unwrap(), 101);
+        assert_eq!(source.block_hash(101).await.unwrap(), BLOCK_HASH);
+
+        let block = source.block(BLOCK_HASH).await.unwrap();
+        assert_eq!(block.id, BLOCK_HASH);
+        assert_eq!(block.height, 101);
+        assert_eq!(block.timestamp, 1_700_000_000);
+        assert_eq!(block.tx_count, 2);
+        assert_eq!(block.mediantime, 1_699_999_000);
+        assert_eq!(block.previousblockhash.as_deref(), Some("0000000000000000000000000000000000000000000000000000000000000064"));
+
+        assert_eq!(source.block_txs(&block).await.unwrap(), esplora_txs());
+    }
+
+    #[tokio::test]
+    async fn reads_blocks_with_user_and_password() {
+        let url = mock_node(USER_PASS_AUTH, true);
+        let source = BitcoindSource::new(test_util::http_client(), &url, RpcAuth::UserPass("user".into(), "pass".into()));
+        assert_block(&source).await;
+    }
+
+    #[tokio::test]
+    async fn reads_blocks_with_cookie_file() {
+        let url = mock_node(COOKIE_AUTH, true);
+        let cookie = std::env::temp_dir().join(format!("ingestion-test-{}.cookie", std::process::id()));
+        fs::write(&cookie, "__cookie__:secret\n").unwrap();
+        let source = BitcoindSource::new(test_util::http_client(), &url, RpcAuth::CookieFile(cookie.display().to_string()));
+        assert_block(&source).await;
+        fs::remove_file(cookie).unwrap();
+    }
+
+    #[tokio::test]
+    async fn looks_up_prevouts_the_node_leaves_out() {
+        let url = mock_node(USER_PASS_AUTH, false);
+        let source = BitcoindSource::new(test_util::http_client(), &url, RpcAuth::UserPass("user".into(), "pass".into()));
+        assert_block(&source).await;
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_credentials() {
+        let url = mock_node(USER_PASS_AUTH, true);
+        let source = BitcoindSource::new(test_util::http_client(), &url, RpcAuth::UserPass("user".into(), "wrong".into()));
+        assert!(source.tip_height().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn skips_mempool_entries_that_left_the_mempool() {
+        let url = mock_node(USER_PASS_AUTH, true);
+        let source = BitcoindSource::new(test_util::http_client(), &url, RpcAuth::UserPass("user".into(), "pass".into()));
+        let txids = vec![SPENT_TXID.to_string(), MEMPOOL_TXID.to_string()];
+
+        let entries = source.mempool_entries(&txids).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].txid, MEMPOOL_TXID);
+        assert_eq!(entries[0].fee, 1_410);
+        assert_eq!(entries[0].vsize, 141);
+        assert_eq!(entries[0].weight, 561);
+    }
+
+    #[tokio::test]
+    async fn fails_mempool_entries_on_other_errors() {
+        let url = mock_node(USER_PASS_AUTH, true);
+        let source = BitcoindSource::new(test_util::http_client(), &url, RpcAuth::UserPass("user".into(), "wrong".into()));
+        assert!(source.mempool_entries(&[MEMPOOL_TXID.to_string()]).await.is_err());
+    }
+}
@@ -0,0 +1,597 @@
+use async_trait::async_trait;
+use bitcoin::consensus::deserialize;
+use bitcoin::hashes::Hash;
+use bitcoin::hex::DisplayHex;
+use bitcoin::{Address, Block, BlockHash, Network, Work};
+use bitcoin::block::Header;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{debug, info};
+
+use super::{script_type, ApiBlockInfo, ApiTransaction, ApiTransactionInput, ApiTransactionOutput, BlockSource, SourceResult};
+
+const COINBASE_PREV_TXID: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+struct BlockLocation {
+    file: PathBuf,
+    // Offset of the serialized block, just past the magic bytes and length prefix.
+    offset: u64,
+    size: u32,
+    prev: BlockHash,
+    time: u32,
+    work: Work,
+    height: i32,
+    // Total work of the chain ending in this block; only meaningful once height is set.
+    chainwork: Work,
+}
+
+#[derive(Default)]
+struct Index {
+    locations: HashMap<BlockHash, BlockLocation>,
+    // Hashes of the best chain, indexed by height.
+    chain: Vec<BlockHash>,
+    // How far each file has been read. bitcoind appends to the newest file and starts new ones,
+    // so a refresh only reads what was added since.
+    scanned: HashMap<PathBuf, u64>,
+}
+
+// Reads blocks straight out of Bitcoin Core's blocks/blk*.dat files. Blocks are stored in arrival
+// order, so heights come from following prev-hash links and, as in bitcoind, the best chain is the
+// one with the most cumulative work. Files are indexed on startup and again, for blocks appended
+// since, whenever the tip is asked for. Prevouts are not in the block files, so spent values and
+// fees are filled in from the outputs already stored. Reading and indexing the files is blocking
+// work, so it runs on the blocking pool.
+pub struct BlkFileSource {
+    files: Arc<BlockFiles>,
+}
+
+struct BlockFiles {
+    network: Network,
+    blocks_dir: PathBuf,
+    xor_key: [u8; 8],
+    index: Mutex<Index>,
+}
+
+fn xor_in_place(buf: &mut [u8], key: &[u8; 8], offset: u64) {
+    if key.iter().all(|b| *b == 0) {
+        return;
+    }
+    for (i, byte) in buf.iter_mut().enumerate() {
+        *byte ^= key[((offset + i as u64) % 8) as usize];
+    }
+}
+
+fn read_at(path: &Path, key: &[u8; 8], offset: u64, len: usize) -> std::io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buf = vec![0u8; len];
+    file.read_exact(&mut buf)?;
+    xor_in_place(&mut buf, key, offset);
+    Ok(buf)
+}
+
+// Reads the blocks in `data`, which starts `offset` bytes into `path`, and returns them with the
+// offset just past the last complete one.
+fn scan(path: &Path, data: &[u8], offset: u64, magic: [u8; 4]) -> SourceResult<(Vec<(BlockHash, BlockLocation)>, u64)> {
+    let mut blocks = Vec::new();
+    let mut pos = 0usize;
+    while pos + 8 <= data.len() {
+        // Block files are preallocated, so the unused tail is zero-filled.
+        if data[pos..pos + 4] == [0, 0, 0, 0] {
+            break;
+        }
+        if data[pos..pos + 4] != magic {
+            return Err(format!("Unexpected magic bytes in {} at offset {}", path.display(), offset + pos as u64).into());
+        }
+        let size = u32::from_le_bytes(data[pos + 4..pos + 8].try_into()?);
+        let start = pos + 8;
+        let end = start + size as usize;
+        if end > data.len() || size < 80 {
+            break;
+        }
+
+        let header: Header = deserialize(&data[start..start + 80])?;
+        blocks.push((header.block_hash(), BlockLocation {
+            file: path.to_path_buf(),
+            offset: offset + start as u64,
+            size,
+            prev: header.prev_blockhash,
+            time: header.time,
+            work: header.work(),
+            height: -1,
+            chainwork: header.work(),
+        }));
+        pos = end;
+    }
+    Ok((blocks, offset + pos as u64))
+}
+
+impl BlkFileSource {
+    pub fn open(blocks_dir: &str, network: Network) -> SourceResult<Self> {
+        Ok(BlkFileSource { files: Arc::new(BlockFiles::open(blocks_dir, network)?) })
+    }
+
+    async fn blocking<T, F>(&self, work: F) -> SourceResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&BlockFiles) -> SourceResult<T> + Send + 'static,
+    {
+        let files = Arc::clone(&self.files);
+        tokio::task::spawn_blocking(move || work(&files)).await?
+    }
+}
+
+impl BlockFiles {
+    fn open(blocks_dir: &str, network: Network) -> SourceResult<Self> {
+        let blocks_dir = Path::new(blocks_dir);
+
+        // Bitcoin Core 28+ obfuscates block files with the 8-byte key in xor.dat.
+        let mut xor_key = [0u8; 8];
+        let xor_path = blocks_dir.join("xor.dat");
+        if xor_path.exists() {
+            let key = fs::read(&xor_path)?;
+            if key.len() != 8 {
+                return Err(format!("{} must contain 8 bytes", xor_path.display()).into());
+            }
+            xor_key.copy_from_slice(&key);
+        }
+
+        let files = BlockFiles {
+            network,
+            blocks_dir: blocks_dir.to_path_buf(),
+            xor_key,
+            index: Mutex::new(Index::default()),
+        };
+        files.refresh()?;
+        let index = files.index.lock().unwrap();
+        info!(blocks = index.locations.len(), tip = index.chain.len() as i32 - 1, "Indexed block files");
+        drop(index);
+        Ok(files)
+    }
+
+    // Indexes blocks written since the last call and moves the best chain to the new tip.
+    fn refresh(&self) -> SourceResult<()> {
+        let mut files: Vec<PathBuf> = fs::read_dir(&self.blocks_dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("blk") && name.ends_with(".dat"))
+            })
+            .collect();
+        files.sort();
+
+        let magic = self.network.magic().to_bytes();
+        let mut index = self.index.lock().unwrap();
+        let mut added = false;
+
+        for path in files {
+            let scanned = index.scanned.get(&path).copied().unwrap_or(0);
+            let len = fs::metadata(&path)?.len();
+            if len <= scanned {
+                continue;
+            }
+            let data = read_at(&path, &self.xor_key, scanned, (len - scanned) as usize)?;
+            let (blocks, scanned_to) = scan(&path, &data, scanned, magic)?;
+            debug!(file = %path.display(), blocks = blocks.len(), "Indexed block file");
+            added |= !blocks.is_empty();
+            index.locations.extend(blocks);
+            index.scanned.insert(path, scanned_to);
+        }
+
+        if added {
+            Self::assign_heights(&mut index);
+        }
+        Ok(())
+    }
+
+    // Fills in heights and chainwork for newly indexed blocks (and any earlier blocks that were
+    // waiting on them) from the prev-hash links, then switches the best chain to the block with
+    // the most work if that changed. Blocks whose ancestry is missing from the files keep height -1.
+    fn assign_heights(index: &mut Index) {
+        let locations = &mut index.locations;
+        // Every block connected in this pass, including children that arrived before their parent
+        // in an earlier refresh, is a tip candidate.
+        let mut connected = Vec::new();
+        // A block that arrived before its parent is connected once the parent shows up.
+        let waiting: Vec<BlockHash> = locations
+            .iter()
+            .filter(|(_, location)| location.height < 0)
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in waiting {
+            let mut path = Vec::new();
+            let mut current = hash;
+            let (mut height, mut chainwork) = loop {
+                match locations.get(&current) {
+                    Some(location) if location.height >= 0 => break (location.height, location.chainwork),
+                    Some(location) if location.prev == BlockHash::all_zeros() => {
+                        path.push(current);
+                        break (-1, Work::from_be_bytes([0; 32]));
+                    }
+                    Some(location) => {
+                        path.push(current);
+                        current = location.prev;
+                    }
+                    None => break (i32::MIN, Work::from_be_bytes([0; 32])),
+                }
+            };
+            if height == i32::MIN {
+                continue;
+            }
+            for hash in path.into_iter().rev() {
+                if let Some(location) = locations.get_mut(&hash) {
+                    height += 1;
+                    chainwork = chainwork + location.work;
+                    location.height = height;
+                    location.chainwork = chainwork;
+                    connected.push(hash);
+                }
+            }
+        }
+
+        // On equal work the block seen first wins, as in bitcoind.
+        connected.sort_by(|a, b| (&locations[a].file, locations[a].offset).cmp(&(&locations[b].file, locations[b].offset)));
+        let current_tip = index.chain.last().copied();
+        let mut tip = current_tip;
+        for hash in current_tip.iter().chain(&connected) {
+            let Some(location) = locations.get(hash).filter(|location| location.height >= 0) else {
+                continue;
+            };
+            let best = tip.and_then(|tip| locations.get(&tip)).map(|best| best.chainwork);
+            if best.is_none_or(|best| location.chainwork > best) {
+                tip = Some(*hash);
+            }
+        }
+        let Some(tip) = tip.filter(|tip| Some(*tip) != current_tip) else {
+            return;
+        };
+
+        // Walk back from the new tip to where it joins the current chain.
+        let mut branch = Vec::new();
+        let mut current = tip;
+        while let Some(location) = locations.get(&current) {
+            if index.chain.get(location.height as usize) == Some(&current) {
+                break;
+            }
+            branch.push(current);
+            current = location.prev;
+        }
+        let fork_height = branch.last().map_or(0, |hash| locations[hash].height as usize);
+        index.chain.truncate(fork_height);
+        index.chain.extend(branch.into_iter().rev());
+    }
+
+    // Reads the block with `hash` and returns it with its height and median time.
+    fn read_block(&self, hash: &str) -> SourceResult<(Block, i32, i64)> {
+        let block_hash: BlockHash = hash.parse()?;
+        let index = self.index.lock().unwrap();
+        let location = index
+            .locations
+            .get(&block_hash)
+            .ok_or_else(|| format!("Block {} not found in block files", hash))?;
+        let data = read_at(&location.file, &self.xor_key, location.offset, location.size as usize)?;
+        Ok((deserialize(&data)?, location.height, Self::median_time(&index.locations, location)))
+    }
+
+    // Median of the timestamps of the block and its ten predecessors, as bitcoind reports it.
+    fn median_time(locations: &HashMap<BlockHash, BlockLocation>, location: &BlockLocation) -> i64 {
+        let mut times = vec![location.time];
+        let mut prev = location.prev;
+        while times.len() < 11 {
+            match locations.get(&prev) {
+                Some(parent) => {
+                    times.push(parent.time);
+                    prev = parent.prev;
+                }
+                None => break,
+            }
+        }
+        times.sort_unstable();
+        times[times.len() / 2] as i64
+    }
+}
+
+#[async_trait]
+impl BlockSource for BlkFileSource {
+    async fn tip_height(&self) -> SourceResult<i32> {
+        self.blocking(|files| {
+            files.refresh()?;
+            Ok(files.index.lock().unwrap().chain.len() as i32 - 1)
+        })
+        .await
+    }
+
+    async fn block_hash(&self, height: i32) -> SourceResult<String> {
+        let index = self.files.index.lock().unwrap();
+        usize::try_from(height)
+            .ok()
+            .and_then(|height| index.chain.get(height))
+            .map(|hash| hash.to_string())
+            .ok_or_else(|| format!("No block at height {} in block files", height).into())
+    }
+
+    async fn block(&self, hash: &str) -> SourceResult<ApiBlockInfo> {
+        let hash = hash.to_string();
+        let (block, height, mediantime) = self.blocking(move |files| files.read_block(&hash)).await?;
+        let header = &block.header;
+
+        Ok(ApiBlockInfo {
+            id: header.block_hash().to_string(),
+            height,
+            timestamp: header.time as i64,
+            tx_count: block.txdata.len() as i32,
+            size: block.total_size() as i32,
+            weight: block.weight().to_wu() as i32,
+            previousblockhash: (header.prev_blockhash != BlockHash::all_zeros()).then(|| header.prev_blockhash.to_string()),
+            mediantime,
+            difficulty: header.difficulty_float(),
+        })
+    }
+
+    async fn block_txs(&self, block: &ApiBlockInfo) -> SourceResult<Vec<ApiTransaction>> {
+        let hash = block.id.clone();
+        let (block, _, _) = self.blocking(move |files| files.read_block(&hash)).await?;
+        let network = self.files.network;
+
+        Ok(block.txdata.iter().map(|tx| ApiTransaction {
+            txid: tx.compute_txid().to_string(),
+            // Known for a coinbase; otherwise store_block works it out from the stored outputs.
+            fee: tx.is_coinbase().then_some(0),
+            weight: tx.weight().to_wu() as i32,
+            vin: tx.input.iter().map(|vin| {
+                let (txid, vout) = if vin.previous_output.is_null() {
+                    (COINBASE_PREV_TXID.to_string(), u32::MAX)
+                } else {
+                    (vin.previous_output.txid.to_string(), vin.previous_output.vout)
+                };
+                ApiTransactionInput {
+                    txid,
+                    vout,
+                    prevout: None,
+                    scriptsig: vin.script_sig.to_hex_string(),
+                    witness: vin.witness.iter().map(|item| item.to_lower_hex_string()).collect(),
+                    is_coinbase: vin.previous_output.is_null(),
+                    sequence: vin.sequence.0 as i64,
+                }
+            }).collect(),
+            vout: tx.output.iter().map(|vout| ApiTransactionOutput {
+                scriptpubkey: vout.script_pubkey.to_hex_string(),
+                scriptpubkey_asm: vout.script_pubkey.to_asm_string(),
+                scriptpubkey_type: script_type(&vout.script_pubkey).to_string(),
+                scriptpubkey_address: Address::from_script(&vout.script_pubkey, network)
+                    .ok()
+                    .map(|address| address.to_string()),
+                value: vout.value.to_sat() as i64,
+            }).collect(),
+        }).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::block::Version;
+    use bitcoin::consensus::serialize;
+    use bitcoin::transaction::{self, Transaction, TxIn, TxOut};
+    use bitcoin::{Amount, CompactTarget, OutPoint, ScriptBuf, Sequence, TxMerkleNode, Witness};
+    use std::io::Write;
+
+    // Regtest's minimum difficulty, and a mainnet-like one with far more work per block.
+    const EASY_BITS: u32 = 0x207fffff;
+    const HARD_BITS: u32 = 0x1d00ffff;
+    const XOR_KEY: [u8; 8] = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88];
+
+    fn block(prev: BlockHash, bits: u32, tag: u8) -> Block {
+        let coinbase = Transaction {
+            version: transaction::Version::ONE,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::from_bytes(vec![1, tag]),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(5_000_000_000),
+                script_pubkey: ScriptBuf::from_hex("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap(),
+            }],
+        };
+        let mut block = Block {
+            header: Header {
+                version: Version::ONE,
+                prev_blockhash: prev,
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 1_600_000_000 + tag as u32 * 600,
+                bits: CompactTarget::from_consensus(bits),
+                nonce: tag as u32,
+            },
+            txdata: vec![coinbase],
+        };
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        block
+    }
+
+    // A fresh, empty blocks directory, obfuscated with XOR_KEY when `xor` is set.
+    fn blocks_dir(name: &str, xor: bool) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ingestion-blk-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        if xor {
+            fs::write(dir.join("xor.dat"), XOR_KEY).unwrap();
+        }
+        dir
+    }
+
+    // Appends `blocks` to `file` the way bitcoind stores them: magic, length, block.
+    fn append_blocks(dir: &Path, file: &str, blocks: &[&Block]) {
+        let key = fs::read(dir.join("xor.dat")).map_or([0; 8], |key| key.try_into().unwrap());
+        let path = dir.join(file);
+        let offset = fs::metadata(&path).map_or(0, |metadata| metadata.len());
+        let mut data = Vec::new();
+        for block in blocks {
+            let bytes = serialize(*block);
+            data.extend_from_slice(&Network::Regtest.magic().to_bytes());
+            data.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            data.extend_from_slice(&bytes);
+        }
+        xor_in_place(&mut data, &key, offset);
+        fs::OpenOptions::new().create(true).append(true).open(path).unwrap().write_all(&data).unwrap();
+    }
+
+    #[test]
+    fn xor_depends_on_the_file_offset() {
+        let plain: Vec<u8> = (0..20).collect();
+        let mut whole = plain.clone();
+        xor_in_place(&mut whole, &XOR_KEY, 0);
+        assert_ne!(whole, plain);
+
+        // Obfuscating a slice that starts part way into the file matches the whole file's bytes.
+        let mut tail = plain[5..].to_vec();
+        xor_in_place(&mut tail, &XOR_KEY, 5);
+        assert_eq!(tail, whole[5..]);
+
+        xor_in_place(&mut whole, &XOR_KEY, 0);
+        assert_eq!(whole, plain);
+    }
+
+    #[test]
+    fn scan_stops_at_zero_fill_and_partial_blocks() {
+        let magic = Network::Regtest.magic().to_bytes();
+        let genesis = serialize(&block(BlockHash::all_zeros(), EASY_BITS, 0));
+        let mut data = magic.to_vec();
+        data.extend_from_slice(&(genesis.len() as u32).to_le_bytes());
+        data.extend_from_slice(&genesis);
+        let first_end = data.len() as u64;
+
+        let mut preallocated = data.clone();
+        preallocated.extend_from_slice(&[0; 64]);
+        let (blocks, scanned) = scan(Path::new("blk00000.dat"), &preallocated, 0, magic).unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].1.offset, 8);
+        assert_eq!(scanned, first_end);
+
+        // A block still being written is left for the next scan.
+        let mut partial = data.clone();
+        partial.extend_from_slice(&magic);
+        partial.extend_from_slice(&1000u32.to_le_bytes());
+        partial.extend_from_slice(&[1; 100]);
+        let (blocks, scanned) = scan(Path::new("blk00000.dat"), &partial, 0, magic).unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(scanned, first_end);
+
+        let mut corrupt = data;
+        corrupt.extend_from_slice(&[1, 2, 3, 4, 0, 0, 0, 0]);
+        assert!(scan(Path::new("blk00000.dat"), &corrupt, 0, magic).is_err());
+    }
+
+    async fn assert_reads_chain(xor: bool) {
+        let dir = blocks_dir(if xor { "xor" } else { "plain" }, xor);
+        let genesis = block(BlockHash::all_zeros(), EASY_BITS, 0);
+        let first = block(genesis.block_hash(), EASY_BITS, 1);
+        let second = block(first.block_hash(), EASY_BITS, 2);
+        // Out of order, as blocks downloaded in parallel end up on disk.
+        append_blocks(&dir, "blk00000.dat", &[&genesis, &second]);
+        append_blocks(&dir, "blk00001.dat", &[&first]);
+
+        let source = BlkFileSource::open(dir.to_str().unwrap(), Network::Regtest).unwrap();
+        assert_eq!(source.tip_height().await.unwrap(), 2);
+        for (height, block) in [&genesis, &first, &second].into_iter().enumerate() {
+            assert_eq!(source.block_hash(height as i32).await.unwrap(), block.block_hash().to_string());
+        }
+
+        let info = source.block(&second.block_hash().to_string()).await.unwrap();
+        assert_eq!(info.height, 2);
+        assert_eq!(info.previousblockhash, Some(first.block_hash().to_string()));
+        assert_eq!(info.size as usize, serialize(&second).len());
+        assert_eq!(info.mediantime, first.header.time as i64);
+
+        let txs = source.block_txs(&info).await.unwrap();
+        assert_eq!(txs.len(), 1);
+        assert_eq!(txs[0].txid, second.txdata[0].compute_txid().to_string());
+        assert!(txs[0].vin[0].is_coinbase);
+        assert_eq!(txs[0].fee, Some(0));
+        assert_eq!(txs[0].vout[0].value, 5_000_000_000);
+        assert_eq!(txs[0].vout[0].scriptpubkey_type, "v0_p2wpkh");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reads_plain_block_files() {
+        assert_reads_chain(false).await;
+    }
+
+    #[tokio::test]
+    async fn reads_obfuscated_block_files() {
+        assert_reads_chain(true).await;
+    }
+
+    #[tokio::test]
+    async fn follows_the_chain_with_most_work() {
+        let dir = blocks_dir("work", false);
+        let genesis = block(BlockHash::all_zeros(), EASY_BITS, 0);
+        let heavy = block(genesis.block_hash(), HARD_BITS, 1);
+        let light = block(genesis.block_hash(), EASY_BITS, 2);
+        let longer = block(light.block_hash(), EASY_BITS, 3);
+        append_blocks(&dir, "blk00000.dat", &[&genesis, &light, &longer, &heavy]);
+
+        let source = BlkFileSource::open(dir.to_str().unwrap(), Network::Regtest).unwrap();
+        assert_eq!(source.tip_height().await.unwrap(), 1);
+        assert_eq!(source.block_hash(1).await.unwrap(), heavy.block_hash().to_string());
+        // The lighter branch is still indexed, so its blocks can be read by hash.
+        assert_eq!(source.block(&longer.block_hash().to_string()).await.unwrap().height, 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn picks_up_blocks_written_after_opening() {
+        let dir = blocks_dir("refresh", true);
+        let genesis = block(BlockHash::all_zeros(), EASY_BITS, 0);
+        let first = block(genesis.block_hash(), EASY_BITS, 1);
+        append_blocks(&dir, "blk00000.dat", &[&genesis]);
+
+        let source = BlkFileSource::open(dir.to_str().unwrap(), Network::Regtest).unwrap();
+        assert_eq!(source.tip_height().await.unwrap(), 0);
+
+        append_blocks(&dir, "blk00000.dat", &[&first]);
+        assert_eq!(source.tip_height().await.unwrap(), 1);
+        let second = block(first.block_hash(), EASY_BITS, 2);
+        append_blocks(&dir, "blk00001.dat", &[&second]);
+        assert_eq!(source.tip_height().await.unwrap(), 2);
+        assert_eq!(source.block_hash(2).await.unwrap(), second.block_hash().to_string());
+
+        // A heavier branch arriving later replaces the blocks above the fork.
+        let heavy = block(genesis.block_hash(), HARD_BITS, 3);
+        append_blocks(&dir, "blk00001.dat", &[&heavy]);
+        assert_eq!(source.tip_height().await.unwrap(), 1);
+        assert_eq!(source.block_hash(1).await.unwrap(), heavy.block_hash().to_string());
+        assert!(source.block_hash(2).await.is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn moves_the_tip_to_children_written_before_their_parent() {
+        let dir = blocks_dir("orphan", false);
+        let genesis = block(BlockHash::all_zeros(), EASY_BITS, 0);
+        let first = block(genesis.block_hash(), EASY_BITS, 1);
+        let second = block(first.block_hash(), EASY_BITS, 2);
+        append_blocks(&dir, "blk00000.dat", &[&genesis, &second]);
+
+        let source = BlkFileSource::open(dir.to_str().unwrap(), Network::Regtest).unwrap();
+        assert_eq!(source.tip_height().await.unwrap(), 0);
+
+        // The child was indexed by an earlier refresh and only now connects.
+        append_blocks(&dir, "blk00000.dat", &[&first]);
+        assert_eq!(source.tip_height().await.unwrap(), 2);
+        assert_eq!(source.block_hash(2).await.unwrap(), second.block_hash().to_string());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,468 @@
+use async_trait::async_trait;
+use reqwest::{Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::http::{HttpClient, HttpError};
+use super::{ApiBlockInfo, ApiTransaction, BlockSource, MempoolEntry, MempoolSource, SourceResult};
+
+#[derive(Deserialize)]
+struct BlockHashResponse {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct RecentMempoolTx {
+    txid: String,
+    fee: i64,
+    vsize: i64,
+}
+
+#[derive(Deserialize)]
+struct MempoolTx {
+    txid: String,
+    fee: i64,
+    weight: i64,
+}
+
+// A mirror's circuit opens after this many failures in a row and stays open for COOLDOWN, after
+// which one request is let through to probe it.
+const FAILURE_THRESHOLD: u32 = 3;
+const COOLDOWN: Duration = Duration::from_secs(60);
+// Mirrors whose tip is more than this many blocks behind the best tip seen are only used when no
+// up-to-date mirror answers.
+const MAX_TIP_LAG: i32 = 2;
+// /tx/:txid lookups made per mempool poll, one at a time, and how long they may take together.
+// The rest are looked up on later polls, so a large backlog reaches public mirrors as a trickle
+// rather than a burst that gets them rate limiting block fetches too.
+const MAX_TX_LOOKUPS: usize = 25;
+const TX_LOOKUP_BUDGET: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+struct MirrorHealth {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    tip: Option<i32>,
+    lagging: bool,
+}
+
+struct Mirror {
+    base_url: String,
+    health: Mutex<MirrorHealth>,
+}
+
+// Esplora REST API client, e.g. https://blockstream.info/api, https://mempool.space/api or a
+// self-hosted instance. Given several base URLs it fails over between them in order, with a
+// circuit breaker per mirror, and cross-checks their tips so a lagging mirror is passed over.
+pub struct EsploraSource {
+    http: Arc<HttpClient>,
+    mirrors: Vec<Mirror>,
+}
+
+impl EsploraSource {
+    pub fn new(http: Arc<HttpClient>, base_urls: &[String]) -> Self {
+        EsploraSource {
+            http,
+            mirrors: base_urls
+                .iter()
+                .map(|base_url| Mirror {
+                    base_url: base_url.trim_end_matches('/').to_string(),
+                    health: Mutex::new(MirrorHealth::default()),
+                })
+                .collect(),
+        }
+    }
+
+    // Mirror indexes to try, best first: healthy and up to date, then lagging, then those whose
+    // circuit is due a probe. Mirrors with an open circuit are skipped unless every one is open.
+    fn candidates(&self) -> Vec<usize> {
+        let now = Instant::now();
+        let mut ranked: Vec<(u8, usize)> = self.mirrors.iter().enumerate().map(|(index, mirror)| {
+            let health = mirror.health.lock().unwrap();
+            let rank = match health.open_until {
+                Some(open_until) if open_until > now => 3,
+                Some(_) => 2,
+                None if health.lagging => 1,
+                None => 0,
+            };
+            (rank, index)
+        }).collect();
+        ranked.sort();
+
+        if ranked.iter().any(|(rank, _)| *rank < 3) {
+            ranked.retain(|(rank, _)| *rank < 3);
+        }
+        ranked.into_iter().map(|(_, index)| index).collect()
+    }
+
+    fn record_success(&self, index: usize) {
+        let mirror = &self.mirrors[index];
+        let mut health = mirror.health.lock().unwrap();
+        if health.open_until.is_some() {
+            info!(mirror = %mirror.base_url, "Esplora mirror recovered, closing its circuit");
+        }
+        health.consecutive_failures = 0;
+        health.open_until = None;
+    }
+
+    // Also forgets the mirror's tip, which can no longer be vouched for.
+    fn record_failure(&self, index: usize, error: &(dyn std::error::Error + Send + Sync)) {
+        let mirror = &self.mirrors[index];
+        let mut health = mirror.health.lock().unwrap();
+        health.consecutive_failures += 1;
+        health.tip = None;
+        warn!(mirror = %mirror.base_url, failures = health.consecutive_failures, error = %error, "Esplora mirror failed");
+        if health.consecutive_failures >= FAILURE_THRESHOLD {
+            if health.open_until.is_none_or(|open_until| open_until <= Instant::now()) {
+                warn!(mirror = %mirror.base_url, cooldown_secs = COOLDOWN.as_secs(), "Opening circuit for Esplora mirror");
+            }
+            health.open_until = Some(Instant::now() + COOLDOWN);
+        }
+    }
+
+    fn best_tip(&self) -> Option<i32> {
+        self.mirrors.iter().filter_map(|mirror| mirror.health.lock().unwrap().tip).max()
+    }
+
+    // GETs `path` from the first mirror that answers. A 404 is a real answer and comes back as
+    // None, unless `exists` says the resource is known to exist upstream (a block at or below the
+    // best tip seen): then the mirror is only behind and the next one is asked, and None means
+    // every mirror answered 404.
+    async fn get_optional(&self, path: &str, exists: bool) -> SourceResult<Option<Response>> {
+        let mut last_error: Option<HttpError> = None;
+        let mut not_found = false;
+        for index in self.candidates() {
+            let url = format!("{}{}", self.mirrors[index].base_url, path);
+            let result = match self.http.get(&url).await {
+                Ok(response) if response.status() == StatusCode::NOT_FOUND => Ok(None),
+                Ok(response) => self.http.checked(&url, response).map(Some),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(None) if exists => {
+                    self.record_success(index);
+                    not_found = true;
+                }
+                Ok(response) => {
+                    self.record_success(index);
+                    return Ok(response);
+                }
+                Err(e) => {
+                    self.record_failure(index, &e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        if not_found {
+            return Ok(None);
+        }
+        Err(last_error.map_or_else(|| "No Esplora mirrors configured".into(), |e| e.into()))
+    }
+
+    // GETs something every up-to-date mirror has, such as a block by hash or the mempool.
+    async fn get(&self, path: &str) -> SourceResult<Response> {
+        self.get_optional(path, true).await?.ok_or_else(|| format!("{} was not found", path).into())
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, path: &str) -> SourceResult<T> {
+        Ok(self.get(path).await?.json::<T>().await?)
+    }
+}
+
+#[async_trait]
+impl BlockSource for EsploraSource {
+    // Asks every available mirror for its tip and returns the highest, marking the mirrors that
+    // fall too far behind it as lagging.
+    async fn tip_height(&self) -> SourceResult<i32> {
+        let mut last_error: Option<Box<dyn std::error::Error + Send + Sync>> = None;
+        for index in self.candidates() {
+            let url = format!("{}/blocks/tip/height", self.mirrors[index].base_url);
+            let result: SourceResult<i32> = match self.http.get_text(&url).await {
+                Ok(text) => text.trim().parse::<i32>().map_err(|e| format!("{} returned an invalid tip {:?}: {}", url, text, e).into()),
+                Err(e) => Err(e.into()),
+            };
+            match result {
+                Ok(tip) => {
+                    self.record_success(index);
+                    self.mirrors[index].health.lock().unwrap().tip = Some(tip);
+                }
+                Err(e) => {
+                    self.record_failure(index, e.as_ref());
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        let Some(best_tip) = self.best_tip() else {
+            return Err(last_error.unwrap_or_else(|| "No Esplora mirrors configured".into()));
+        };
+
+        for mirror in &self.mirrors {
+            let mut health = mirror.health.lock().unwrap();
+            let lagging = health.tip.is_some_and(|tip| best_tip - tip > MAX_TIP_LAG);
+            if lagging != health.lagging {
+                if lagging {
+                    warn!(mirror = %mirror.base_url, tip = ?health.tip, best_tip, "Esplora mirror is lagging");
+                } else {
+                    info!(mirror = %mirror.base_url, "Esplora mirror caught up");
+                }
+                health.lagging = lagging;
+            }
+        }
+
+        Ok(best_tip)
+    }
+
+    async fn block_hash(&self, height: i32) -> SourceResult<String> {
+        let path = format!("/block-height/{}", height);
+        let exists = self.best_tip().is_some_and(|tip| height <= tip);
+        let response = self.get_optional(&path, exists).await?.ok_or_else(|| format!("{} was not found", path))?;
+        let hash_text = response.text().await?;
+        let hash_info = BlockHashResponse { id: hash_text.trim().to_string() };
+        Ok(hash_info.id)
+    }
+
+    async fn block(&self, hash: &str) -> SourceResult<ApiBlockInfo> {
+        self.get_json::<ApiBlockInfo>(&format!("/block/{}", hash)).await
+    }
+
+    // /block/:hash/txs only returns 25 transactions per call, so page through it with
+    // /block/:hash/txs/:start_index until tx_count transactions have been collected.
+    async fn block_txs(&self, block: &ApiBlockInfo) -> SourceResult<Vec<ApiTransaction>> {
+        let tx_count = block.tx_count as usize;
+        let mut txs = Vec::with_capacity(tx_count);
+
+        while txs.len() < tx_count {
+            let page = self.get_json::<Vec<ApiTransaction>>(&format!("/block/{}/txs/{}", block.id, txs.len())).await?;
+            if page.is_empty() {
+                break;
+            }
+            txs.extend(page);
+        }
+
+        Ok(txs)
+    }
+}
+
+#[async_trait]
+impl MempoolSource for EsploraSource {
+    async fn mempool_txids(&self) -> SourceResult<Vec<String>> {
+        self.get_json::<Vec<String>>("/mempool/txids").await
+    }
+
+    // /mempool/recent covers the newest arrivals in one call; anything else is looked up with
+    // /tx/:txid, up to MAX_TX_LOOKUPS and TX_LOOKUP_BUDGET per call.
+    async fn mempool_entries(&self, txids: &[String]) -> SourceResult<Vec<MempoolEntry>> {
+        let recent = self.get_json::<Vec<RecentMempoolTx>>("/mempool/recent").await?;
+
+        let wanted: HashSet<&String> = txids.iter().collect();
+        let mut entries: Vec<MempoolEntry> = recent
+            .into_iter()
+            .filter(|tx| wanted.contains(&tx.txid))
+            .map(|tx| MempoolEntry { txid: tx.txid, fee: tx.fee, vsize: tx.vsize, weight: tx.vsize * 4 })
+            .collect();
+        let found: HashSet<String> = entries.iter().map(|entry| entry.txid.clone()).collect();
+
+        let started = Instant::now();
+        for txid in txids.iter().filter(|txid| !found.contains(*txid)).take(MAX_TX_LOOKUPS) {
+            if started.elapsed() >= TX_LOOKUP_BUDGET {
+                break;
+            }
+            let response = match self.get_optional(&format!("/tx/{}", txid), false).await {
+                Ok(Some(response)) => response,
+                Ok(None) => continue,
+                Err(e) if entries.is_empty() => return Err(e),
+                // Keep what was found; asking for more now would only add to the mirrors' load.
+                Err(e) => {
+                    warn!(error = %e, found = entries.len(), "Stopping mempool lookups until the next poll");
+                    break;
+                }
+            };
+            let tx = response.json::<MempoolTx>().await?;
+            entries.push(MempoolEntry {
+                txid: tx.txid,
+                fee: tx.fee,
+                vsize: (tx.weight + 3) / 4,
+                weight: tx.weight,
+            });
+        }
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use serde_json::{json, Value};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use warp::http::StatusCode;
+    use warp::path::FullPath;
+    use warp::Filter;
+
+    const BLOCK_HASH: &str = "00000000000000000000000000000000000000000000000000000000000000aa";
+
+    fn block_info(tx_count: i32) -> ApiBlockInfo {
+        serde_json::from_value(json!({
+            "id": BLOCK_HASH,
+            "height": 100,
+            "timestamp": 1_700_000_000,
+            "tx_count": tx_count,
+            "size": 1000,
+            "weight": 4000,
+            "previousblockhash": null,
+            "mediantime": 1_699_999_000,
+            "difficulty": 1.0,
+        }))
+        .unwrap()
+    }
+
+    fn tx(index: usize) -> Value {
+        json!({ "txid": format!("{:064x}", index), "fee": 0, "weight": 400, "vin": [], "vout": [] })
+    }
+
+    #[tokio::test]
+    async fn pages_through_block_transactions() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+        // Esplora's 25-transaction pages of a 30-transaction block.
+        let route = warp::path!("block" / String / "txs" / usize).map(move |_hash: String, start: usize| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            warp::reply::json(&(start..30.min(start + 25)).map(tx).collect::<Vec<_>>())
+        });
+        let source = EsploraSource::new(test_util::http_client(), &[test_util::serve(route)]);
+
+        let txs = source.block_txs(&block_info(30)).await.unwrap();
+        assert_eq!(txs.len(), 30);
+        assert_eq!(txs[29].txid, format!("{:064x}", 29));
+        // The block itself is not fetched again just to learn its transaction count.
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    // A mirror with no recent mempool arrivals. Lookups of the first `healthy` txids from
+    // `txids` succeed and the rest answer HTTP 429.
+    fn mock_mempool(healthy: usize) -> (EsploraSource, Arc<AtomicUsize>) {
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&lookups);
+        let recent = warp::path!("mempool" / "recent").map(|| warp::reply::json(&Vec::<Value>::new()));
+        let lookup = warp::path!("tx" / String).map(move |txid: String| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let index = usize::from_str_radix(&txid, 16).unwrap();
+            let (body, status) = if index < healthy {
+                (json!({ "txid": txid, "fee": 1_000, "weight": 560 }), StatusCode::OK)
+            } else {
+                (json!({}), StatusCode::TOO_MANY_REQUESTS)
+            };
+            warp::reply::with_status(warp::reply::json(&body), status)
+        });
+        let source = EsploraSource::new(test_util::http_client(), &[test_util::serve(recent.or(lookup))]);
+        (source, lookups)
+    }
+
+    fn txids(count: usize) -> Vec<String> {
+        (0..count).map(|index| format!("{:064x}", index)).collect()
+    }
+
+    #[tokio::test]
+    async fn looks_up_a_limited_number_of_mempool_transactions_per_poll() {
+        let (source, lookups) = mock_mempool(100);
+        let entries = source.mempool_entries(&txids(100)).await.unwrap();
+        assert_eq!(entries.len(), MAX_TX_LOOKUPS);
+        assert_eq!(lookups.load(Ordering::SeqCst), MAX_TX_LOOKUPS);
+        assert_eq!(entries[0].vsize, 140);
+    }
+
+    #[tokio::test]
+    async fn stops_mempool_lookups_at_the_first_failure() {
+        let (source, lookups) = mock_mempool(3);
+        let entries = source.mempool_entries(&txids(10)).await.unwrap();
+        assert_eq!(entries.len(), 3);
+        // The failing lookup is retried by the client, then nothing more is asked for.
+        assert_eq!(lookups.load(Ordering::SeqCst), 3 + 3);
+
+        let (source, _) = mock_mempool(0);
+        assert!(source.mempool_entries(&txids(10)).await.is_err());
+    }
+
+    struct MockMirror {
+        url: String,
+        requests: Arc<AtomicUsize>,
+        down: Arc<AtomicBool>,
+    }
+
+    // A mirror at `tip` whose /block-height answers for heights up to `indexed`, which can lag
+    // behind `tip` the way a mirror still indexing does. While `down` it answers HTTP 500.
+    fn mock_mirror(tip: &'static str, indexed: i32) -> MockMirror {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let down = Arc::new(AtomicBool::new(false));
+        let (counter, is_down) = (Arc::clone(&requests), Arc::clone(&down));
+        let route = warp::path::full().map(move |path: FullPath| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let height = path.as_str().strip_prefix("/block-height/").and_then(|height| height.parse::<i32>().ok());
+            let (body, status) = match (path.as_str(), height) {
+                _ if is_down.load(Ordering::SeqCst) => (String::new(), StatusCode::INTERNAL_SERVER_ERROR),
+                ("/blocks/tip/height", _) => (tip.to_string(), StatusCode::OK),
+                (_, Some(height)) if height <= indexed => (format!("{:064x}", height), StatusCode::OK),
+                _ => ("Block not found".to_string(), StatusCode::NOT_FOUND),
+            };
+            warp::reply::with_status(body, status)
+        });
+        MockMirror { url: test_util::serve(route), requests, down }
+    }
+
+    fn source(mirrors: &[&MockMirror]) -> EsploraSource {
+        let urls: Vec<String> = mirrors.iter().map(|mirror| mirror.url.clone()).collect();
+        EsploraSource::new(test_util::http_client(), &urls)
+    }
+
+    #[tokio::test]
+    async fn asks_the_next_mirror_for_a_block_at_or_below_the_best_tip() {
+        let behind = mock_mirror("101", 100);
+        let ahead = mock_mirror("102", 102);
+        let source = source(&[&behind, &ahead]);
+        assert_eq!(source.tip_height().await.unwrap(), 102);
+
+        assert_eq!(source.block_hash(102).await.unwrap(), format!("{:064x}", 102));
+        assert_eq!(behind.requests.load(Ordering::SeqCst), 2);
+        assert_eq!(ahead.requests.load(Ordering::SeqCst), 2);
+
+        // Above every tip a 404 is the answer.
+        assert!(source.block_hash(103).await.is_err());
+        assert_eq!(behind.requests.load(Ordering::SeqCst), 3);
+        assert_eq!(ahead.requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn forgets_the_tip_of_a_failing_mirror() {
+        let stale = mock_mirror("200", 200);
+        let healthy = mock_mirror("150", 150);
+        let source = source(&[&stale, &healthy]);
+        assert_eq!(source.tip_height().await.unwrap(), 200);
+        assert!(source.mirrors[1].health.lock().unwrap().lagging);
+
+        stale.down.store(true, Ordering::SeqCst);
+        assert_eq!(source.tip_height().await.unwrap(), 150);
+        assert_eq!(source.mirrors[0].health.lock().unwrap().tip, None);
+        assert!(!source.mirrors[1].health.lock().unwrap().lagging);
+    }
+
+    #[tokio::test]
+    async fn counts_an_unparseable_tip_as_a_failure() {
+        let broken = mock_mirror("<html>", 0);
+        let healthy = mock_mirror("150", 150);
+        let source = source(&[&broken, &healthy]);
+        for failures in 1..=FAILURE_THRESHOLD {
+            assert_eq!(source.tip_height().await.unwrap(), 150);
+            assert_eq!(source.mirrors[0].health.lock().unwrap().consecutive_failures, failures);
+        }
+
+        // The circuit is open now, so the broken mirror is left alone.
+        assert_eq!(source.tip_height().await.unwrap(), 150);
+        assert_eq!(broken.requests.load(Ordering::SeqCst), FAILURE_THRESHOLD as usize);
+    }
+}
@@ -0,0 +1,108 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::error::Error;
+
+mod bitcoind;
+mod blkfile;
+mod esplora;
+pub use bitcoind::{BitcoindSource, RpcAuth};
+pub use blkfile::BlkFileSource;
+pub use esplora::EsploraSource;
+
+pub type SourceResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+// Where blocks come from. The block and transaction models below follow the Esplora JSON
+// schema; other backends convert into them so the persistence code only deals with one shape.
+#[async_trait]
+pub trait BlockSource: Send + Sync {
+    async fn tip_height(&self) -> SourceResult<i32>;
+    async fn block_hash(&self, height: i32) -> SourceResult<String>;
+    async fn block(&self, hash: &str) -> SourceResult<ApiBlockInfo>;
+    // Transactions of a block already fetched with `block`.
+    async fn block_txs(&self, block: &ApiBlockInfo) -> SourceResult<Vec<ApiTransaction>>;
+}
+
+// Unconfirmed transactions, for backends that can see a mempool.
+#[async_trait]
+pub trait MempoolSource: Send + Sync {
+    async fn mempool_txids(&self) -> SourceResult<Vec<String>>;
+    // Details for the given txids; entries that left the mempool in the meantime are omitted.
+    async fn mempool_entries(&self, txids: &[String]) -> SourceResult<Vec<MempoolEntry>>;
+}
+
+pub struct MempoolEntry {
+    pub txid: String,
+    pub fee: i64,
+    pub vsize: i64,
+    pub weight: i64,
+}
+
+#[derive(Deserialize)]
+pub struct ApiBlockInfo {
+    pub id: String,
+    pub height: i32,
+    pub timestamp: i64,
+    pub tx_count: i32,
+    pub size: i32,
+    pub weight: i32,
+    pub previousblockhash: Option<String>,
+    pub mediantime: i64,
+    pub difficulty: f64,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct ApiTransaction {
+    pub txid: String,
+    // None when the source cannot tell without the outputs being spent.
+    pub fee: Option<i64>,
+    pub weight: i32,
+    pub vin: Vec<ApiTransactionInput>,
+    pub vout: Vec<ApiTransactionOutput>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct ApiTransactionInput {
+    pub txid: String,
+    pub vout: u32,
+    pub prevout: Option<ApiTransactionOutput>,
+    pub scriptsig: String,
+    #[serde(default)]
+    pub witness: Vec<String>,
+    pub is_coinbase: bool,
+    pub sequence: i64,
+}
+
+// Esplora's output object; also used for an input's prevout. Values are in satoshis.
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct ApiTransactionOutput {
+    pub scriptpubkey: String,
+    pub scriptpubkey_asm: String,
+    pub scriptpubkey_type: String,
+    pub scriptpubkey_address: Option<String>,
+    pub value: i64,
+}
+
+// Script type names as Esplora reports them in scriptpubkey_type.
+pub fn script_type(script: &bitcoin::Script) -> &'static str {
+    if script.is_empty() {
+        "empty"
+    } else if script.is_p2pk() {
+        "p2pk"
+    } else if script.is_p2pkh() {
+        "p2pkh"
+    } else if script.is_p2sh() {
+        "p2sh"
+    } else if script.is_p2wpkh() {
+        "v0_p2wpkh"
+    } else if script.is_p2wsh() {
+        "v0_p2wsh"
+    } else if script.is_p2tr() {
+        "v1_p2tr"
+    } else if script.is_op_return() {
+        "op_return"
+    } else if script.is_multisig() {
+        "multisig"
+    } else {
+        "unknown"
+    }
+}
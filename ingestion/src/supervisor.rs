@@ -0,0 +1,312 @@
+use serde::Serialize;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration, Instant};
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::config::SupervisorConfig;
+use crate::metrics;
+
+// What a worker returns. An error counts as a crash, like a panic, and the worker is restarted.
+pub type WorkerResult = Result<(), Box<dyn Error + Send + Sync>>;
+
+// A worker that stays up this long is considered healthy again, so its next crash starts over
+// from the initial backoff.
+const STABLE_AFTER: Duration = Duration::from_secs(300);
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkerState {
+    Running,
+    // Crashed and waiting out its backoff before the next start.
+    Restarting,
+    // Returned on its own, e.g. a backfill that reached its end height.
+    Finished,
+    // Returned because of a shutdown.
+    Stopped,
+}
+
+#[derive(Clone, Serialize)]
+pub struct WorkerStatus {
+    pub name: &'static str,
+    pub state: WorkerState,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+}
+
+// Handed to every worker. Loops wait on it alongside their interval and return once it fires,
+// so shutdown never interrupts a block that is being written.
+#[derive(Clone)]
+pub struct Shutdown {
+    receiver: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    pub fn is_triggered(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    pub async fn wait(&mut self) {
+        // The sender lives in the supervisor for as long as the process runs.
+        let _ = self.receiver.wait_for(|triggered| *triggered).await;
+    }
+}
+
+// Runs the background workers, restarting any that panic or fail with exponential backoff, and
+// stops them all on shutdown.
+pub struct Supervisor {
+    config: SupervisorConfig,
+    statuses: Arc<Mutex<BTreeMap<&'static str, WorkerStatus>>>,
+    shutdown: watch::Sender<bool>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Supervisor {
+    pub fn new(config: SupervisorConfig) -> Self {
+        Supervisor {
+            config,
+            statuses: Arc::new(Mutex::new(BTreeMap::new())),
+            shutdown: watch::Sender::new(false),
+            tasks: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn shutdown_handle(&self) -> Shutdown {
+        Shutdown { receiver: self.shutdown.subscribe() }
+    }
+
+    // Starts `worker` under the name `name`. The closure is called again for every restart, so it
+    // has to clone whatever the worker needs rather than move it.
+    pub fn spawn<F, Fut>(&self, name: &'static str, worker: F)
+    where
+        F: Fn(Shutdown) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = WorkerResult> + Send + 'static,
+    {
+        let statuses = Arc::clone(&self.statuses);
+        let shutdown = self.shutdown_handle();
+        let initial_backoff = self.config.restart_backoff;
+        let max_backoff = self.config.max_restart_backoff;
+        let set_state = move |state: WorkerState, last_error: Option<String>| {
+            let mut statuses = statuses.lock().unwrap();
+            let status = statuses.entry(name).or_insert(WorkerStatus { name, state, restarts: 0, last_error: None });
+            status.state = state;
+            if let Some(last_error) = last_error {
+                status.restarts += 1;
+                status.last_error = Some(last_error);
+            }
+        };
+        set_state(WorkerState::Running, None);
+
+        let task = tokio::spawn(async move {
+            let mut shutdown = shutdown;
+            let mut backoff = initial_backoff;
+            loop {
+                set_state(WorkerState::Running, None);
+                metrics::WORKER_UP.with_label_values(&[name]).set(1);
+                let started = Instant::now();
+
+                // Run in its own task so a panic surfaces here as a JoinError instead of
+                // unwinding through the supervisor.
+                let result = tokio::spawn(worker(shutdown.clone()).instrument(info_span!("worker", task = name))).await;
+                metrics::WORKER_UP.with_label_values(&[name]).set(0);
+
+                let message = match result {
+                    Ok(Ok(())) if shutdown.is_triggered() => {
+                        set_state(WorkerState::Stopped, None);
+                        info!(worker = name, "Worker stopped");
+                        return;
+                    }
+                    Ok(Ok(())) => {
+                        set_state(WorkerState::Finished, None);
+                        info!(worker = name, "Worker finished");
+                        return;
+                    }
+                    Ok(Err(e)) => e.to_string(),
+                    Err(e) if e.is_panic() => panic_message(e.into_panic()),
+                    Err(e) => e.to_string(),
+                };
+
+                if started.elapsed() >= STABLE_AFTER {
+                    backoff = initial_backoff;
+                }
+                metrics::WORKER_RESTARTS.with_label_values(&[name]).inc();
+                set_state(WorkerState::Restarting, Some(message.clone()));
+                error!(worker = name, error = %message, backoff = ?backoff, "Worker crashed, restarting");
+
+                tokio::select! {
+                    _ = time::sleep(backoff) => {}
+                    _ = shutdown.wait() => {
+                        set_state(WorkerState::Stopped, None);
+                        info!(worker = name, "Worker stopped");
+                        return;
+                    }
+                }
+                backoff = (backoff * 2).min(max_backoff);
+            }
+        });
+        self.tasks.lock().unwrap().push(task);
+    }
+
+    pub fn statuses(&self) -> Vec<WorkerStatus> {
+        self.statuses.lock().unwrap().values().cloned().collect()
+    }
+
+    // Tells every worker to stop after its current iteration.
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    // Waits for every worker to return after `shutdown`.
+    pub async fn join(&self) {
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        for task in tasks {
+            if let Err(e) = task.await {
+                warn!(error = %e, "Supervisor task failed");
+            }
+        }
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "panicked".to_string()
+    }
+}
+
+// Resolves on SIGTERM or Ctrl-C.
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!(error = %e, "Error listening for Ctrl-C");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!(error = %e, "Error listening for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Received Ctrl-C"),
+        _ = terminate => info!("Received SIGTERM"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn supervisor(restart_backoff: Duration, max_restart_backoff: Duration) -> Supervisor {
+        Supervisor::new(SupervisorConfig { restart_backoff, max_restart_backoff, shutdown_timeout: Duration::from_secs(1) })
+    }
+
+    fn status(supervisor: &Supervisor) -> WorkerStatus {
+        supervisor.statuses().into_iter().next().unwrap()
+    }
+
+    // Lets the paused clock run until the worker has reached `state` with `restarts` restarts.
+    async fn wait_for(supervisor: &Supervisor, state: WorkerState, restarts: u32) {
+        while !(status(supervisor).state == state && status(supervisor).restarts == restarts) {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restarts_panicking_workers_with_backoff() {
+        let supervisor = supervisor(Duration::from_secs(1), Duration::from_secs(2));
+        let starts = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&starts);
+        supervisor.spawn("panicking", move |_| {
+            let starts = Arc::clone(&recorded);
+            async move {
+                let run = {
+                    let mut starts = starts.lock().unwrap();
+                    starts.push(Instant::now());
+                    starts.len()
+                };
+                match run {
+                    1..=3 => panic!("run {} failed", run),
+                    // Up long enough to count as healthy again before crashing.
+                    4 => {
+                        time::sleep(STABLE_AFTER).await;
+                        panic!("run 4 failed");
+                    }
+                    _ => Ok(()),
+                }
+            }
+        });
+        supervisor.join().await;
+
+        let starts = starts.lock().unwrap();
+        let gaps: Vec<Duration> = starts.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        // Doubling up to the ceiling, then back to the initial backoff after the stable run.
+        assert_eq!(
+            gaps,
+            [Duration::from_secs(1), Duration::from_secs(2), Duration::from_secs(2), STABLE_AFTER + Duration::from_secs(1)]
+        );
+        let status = status(&supervisor);
+        assert!(status.state == WorkerState::Finished);
+        assert_eq!(status.restarts, 4);
+        assert_eq!(status.last_error.as_deref(), Some("run 4 failed"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restarts_workers_that_return_errors_and_stops_them_on_shutdown() {
+        let supervisor = supervisor(Duration::from_secs(1), Duration::from_secs(1));
+        let runs = Arc::new(AtomicU32::new(0));
+        let counted = Arc::clone(&runs);
+        supervisor.spawn("erroring", move |mut shutdown| {
+            let runs = Arc::clone(&counted);
+            async move {
+                if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return Err("database unavailable".into());
+                }
+                shutdown.wait().await;
+                Ok(())
+            }
+        });
+
+        wait_for(&supervisor, WorkerState::Running, 1).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(status(&supervisor).last_error.as_deref(), Some("database unavailable"));
+
+        supervisor.shutdown();
+        supervisor.join().await;
+        assert!(status(&supervisor).state == WorkerState::Stopped);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_workers_waiting_out_their_backoff() {
+        let supervisor = supervisor(Duration::from_secs(3600), Duration::from_secs(3600));
+        supervisor.spawn("failing", |_| async { Err("always fails".into()) });
+
+        wait_for(&supervisor, WorkerState::Restarting, 1).await;
+        let started = Instant::now();
+        supervisor.shutdown();
+        supervisor.join().await;
+        assert!(started.elapsed() < Duration::from_secs(1), "took {:?}", started.elapsed());
+        assert!(status(&supervisor).state == WorkerState::Stopped);
+        assert_eq!(status(&supervisor).restarts, 1);
+    }
+}
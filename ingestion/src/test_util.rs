@@ -0,0 +1,44 @@
+use std::sync::{Arc, Mutex};
+use tokio::time::Duration;
+use warp::http::StatusCode;
+use warp::path::FullPath;
+use warp::Filter;
+
+use crate::config::HttpConfig;
+use crate::http::HttpClient;
+
+// A client that retries quickly, so tests exercising the retry path stay fast.
+pub fn http_client() -> Arc<HttpClient> {
+    Arc::new(HttpClient::new(&HttpConfig {
+        timeout: Duration::from_secs(5),
+        max_retries: 2,
+        backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(5),
+        max_per_host: 4,
+    }))
+}
+
+// Serves `filter` on a free local port for the rest of the test and returns its base URL.
+pub fn serve<F>(filter: F) -> String
+where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: warp::Reply,
+{
+    let (addr, server) = warp::serve(filter).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    format!("http://{}", addr)
+}
+
+// Answers every request with `status` and `body`, recording the path and query of each one.
+pub fn serve_response(status: StatusCode, body: &'static str) -> (String, Arc<Mutex<Vec<String>>>) {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&requests);
+    let route = warp::path::full()
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .map(move |path: FullPath, query: String| {
+            let request = if query.is_empty() { path.as_str().to_string() } else { format!("{}?{}", path.as_str(), query) };
+            seen.lock().unwrap().push(request);
+            warp::reply::with_status(body, status)
+        });
+    (serve(route), requests)
+}
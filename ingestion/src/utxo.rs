@@ -0,0 +1,119 @@
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager};
+use diesel::sql_types::{BigInt, Integer, Varchar};
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::debug;
+
+use crate::error::{reject, with_connection, CONNECTION_TIMEOUT};
+use crate::schema::{block_info, utxos};
+
+// Adds the outputs of the block at `height` to the UTXO set and removes the outputs its inputs
+// spend. Outputs already spent by a stored input are skipped, so the set stays right even when
+// blocks are not connected in height order. OP_RETURN outputs can never be spent and are left out.
+pub fn connect_block(conn: &mut PgConnection, height: i32) -> QueryResult<()> {
+    diesel::sql_query(
+        "INSERT INTO utxos (txid, vout, value, script_type, address, height)
+         SELECT t.hash, o.vout, o.value, o.script_type, o.address, t.block_height
+         FROM transaction_outputs o
+         JOIN transactions t ON t.id = o.transaction_id
+         WHERE t.block_height = $1
+           AND o.script_type <> 'op_return'
+           AND NOT EXISTS (
+               SELECT 1 FROM transaction_inputs i
+               WHERE i.previous_output = t.hash AND i.previous_vout = o.vout
+           )
+         ON CONFLICT DO NOTHING",
+    )
+    .bind::<Integer, _>(height)
+    .execute(conn)?;
+
+    diesel::sql_query(
+        "DELETE FROM utxos u
+         USING transaction_inputs i
+         JOIN transactions t ON t.id = i.transaction_id
+         WHERE t.block_height = $1
+           AND NOT i.is_coinbase
+           AND u.txid = i.previous_output
+           AND u.vout = i.previous_vout",
+    )
+    .bind::<Integer, _>(height)
+    .execute(conn)?;
+
+    Ok(())
+}
+
+// Undoes connect_block for every block above `fork_height`: outputs those blocks spent come back
+// and outputs they created go away. Must run before their transactions are deleted. A spent txid
+// stored twice, as the two coinbases repeated before BIP30 are, is restored from its earliest copy,
+// the one connect_block keeps in the set.
+pub fn disconnect_blocks_above(conn: &mut PgConnection, fork_height: i32) -> QueryResult<()> {
+    diesel::sql_query(
+        "INSERT INTO utxos (txid, vout, value, script_type, address, height)
+         SELECT pt.hash, o.vout, o.value, o.script_type, o.address, pt.block_height
+         FROM transaction_inputs i
+         JOIN transactions t ON t.id = i.transaction_id
+         JOIN transactions pt ON pt.hash = i.previous_output
+         JOIN transaction_outputs o ON o.transaction_id = pt.id AND o.vout = i.previous_vout
+         WHERE t.block_height > $1
+           AND pt.block_height <= $1
+           AND NOT i.is_coinbase
+           AND NOT EXISTS (SELECT 1 FROM transactions e WHERE e.hash = pt.hash AND e.block_height < pt.block_height)
+         ON CONFLICT DO NOTHING",
+    )
+    .bind::<Integer, _>(fork_height)
+    .execute(conn)?;
+
+    diesel::delete(utxos::table.filter(utxos::height.gt(fork_height))).execute(conn)?;
+
+    Ok(())
+}
+
+#[derive(QueryableByName, Serialize)]
+pub struct ScriptTypeTotals {
+    #[diesel(sql_type = Varchar)]
+    pub script_type: String,
+    #[diesel(sql_type = BigInt)]
+    pub count: i64,
+    #[diesel(sql_type = BigInt)]
+    pub total_value: i64,
+}
+
+#[derive(Serialize)]
+pub struct UtxoSetTotals {
+    pub height: Option<i32>,
+    pub count: i64,
+    pub total_value: i64,
+    pub by_script_type: Vec<ScriptTypeTotals>,
+}
+
+pub async fn handle_get_utxo_set(
+    pool: Arc<r2d2::Pool<ConnectionManager<PgConnection>>>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    debug!("Handling get UTXO set totals...");
+    let totals = with_connection(pool, CONNECTION_TIMEOUT, |conn| {
+        let height: Option<i32> = block_info::table
+            .filter(block_info::complete.eq(true))
+            .select(diesel::dsl::max(block_info::height))
+            .first::<Option<i32>>(conn)?;
+
+        let by_script_type: Vec<ScriptTypeTotals> = diesel::sql_query(
+            "SELECT script_type, COUNT(*) AS count, COALESCE(SUM(value), 0)::BIGINT AS total_value
+             FROM utxos
+             GROUP BY script_type
+             ORDER BY total_value DESC",
+        )
+        .load(conn)?;
+
+        Ok(UtxoSetTotals {
+            height,
+            count: by_script_type.iter().map(|totals| totals.count).sum(),
+            total_value: by_script_type.iter().map(|totals| totals.total_value).sum(),
+            by_script_type,
+        })
+    })
+    .await
+    .map_err(reject)?;
+
+    Ok(warp::reply::json(&totals))
+}
@@ -7,7 +7,11 @@ CREATE TABLE block_info (
                             timestamp TIMESTAMP NOT NULL,
                             size INT NOT NULL,
                             weight INT NOT NULL,
-                            CONSTRAINT unique_height UNIQUE (height)
+                            hash VARCHAR,
+                            previous_hash VARCHAR,
+                            complete BOOLEAN NOT NULL DEFAULT FALSE,
+                            CONSTRAINT unique_height UNIQUE (height),
+                            CONSTRAINT unique_hash UNIQUE (hash)
 );
 
 CREATE TABLE transactions (
@@ -15,28 +19,117 @@ CREATE TABLE transactions (
                               block_height INT NOT NULL,
                               hash VARCHAR NOT NULL,
                               btc DOUBLE PRECISION NOT NULL,
-                              fee BIGINT NOT NULL,
+                              fee BIGINT,
                               time BIGINT NOT NULL,
-                              FOREIGN KEY (block_height) REFERENCES block_info (height)
+                              vsize INT NOT NULL DEFAULT 0,
+                              weight INT NOT NULL DEFAULT 0,
+                              FOREIGN KEY (block_height) REFERENCES block_info (height),
+                              CONSTRAINT unique_tx_hash_height UNIQUE (hash, block_height)
 );
 
+CREATE INDEX transactions_block_height_idx ON transactions (block_height);
+
 CREATE TABLE transaction_inputs (
                                     id SERIAL PRIMARY KEY,
                                     transaction_id INT NOT NULL,
                                     previous_output VARCHAR NOT NULL,
-                                    value BIGINT NOT NULL,
-                                    FOREIGN KEY (transaction_id) REFERENCES transactions (id)
+                                    value BIGINT,
+                                    vin INT NOT NULL,
+                                    previous_vout INT,
+                                    sequence BIGINT NOT NULL,
+                                    scriptsig VARCHAR NOT NULL,
+                                    witness TEXT[] NOT NULL DEFAULT '{}',
+                                    is_coinbase BOOLEAN NOT NULL,
+                                    FOREIGN KEY (transaction_id) REFERENCES transactions (id),
+                                    CONSTRAINT unique_input UNIQUE (transaction_id, vin)
 );
 
+CREATE INDEX transaction_inputs_outpoint_idx ON transaction_inputs (previous_output, previous_vout);
+
 CREATE TABLE transaction_outputs (
                                      id SERIAL PRIMARY KEY,
                                      transaction_id INT NOT NULL,
                                      address VARCHAR NOT NULL,
                                      value BIGINT NOT NULL,
-                                     FOREIGN KEY (transaction_id) REFERENCES transactions (id)
+                                     vout INT NOT NULL,
+                                     script_hex VARCHAR NOT NULL DEFAULT '',
+                                     script_asm VARCHAR NOT NULL DEFAULT '',
+                                     script_type VARCHAR NOT NULL DEFAULT '',
+                                     FOREIGN KEY (transaction_id) REFERENCES transactions (id),
+                                     CONSTRAINT unique_outpoint UNIQUE (transaction_id, vout)
+);
+
+CREATE TABLE utxos (
+                       txid VARCHAR NOT NULL,
+                       vout INT NOT NULL,
+                       value BIGINT NOT NULL,
+                       script_type VARCHAR NOT NULL,
+                       address VARCHAR NOT NULL,
+                       height INT NOT NULL,
+                       PRIMARY KEY (txid, vout)
+);
+
+CREATE INDEX utxos_height_idx ON utxos (height);
+
+CREATE TABLE addresses (
+                           address VARCHAR PRIMARY KEY,
+                           funded_txo_count BIGINT NOT NULL,
+                           funded_txo_sum BIGINT NOT NULL,
+                           spent_txo_count BIGINT NOT NULL,
+                           spent_txo_sum BIGINT NOT NULL,
+                           tx_count BIGINT NOT NULL,
+                           first_seen_height INT NOT NULL,
+                           last_seen_height INT NOT NULL
+);
+
+CREATE INDEX transaction_outputs_address_idx ON transaction_outputs (address);
+
+CREATE TABLE mempool_transactions (
+                                      txid VARCHAR PRIMARY KEY,
+                                      fee BIGINT NOT NULL,
+                                      vsize BIGINT NOT NULL,
+                                      weight BIGINT NOT NULL,
+                                      first_seen TIMESTAMP NOT NULL,
+                                      confirmed_height INT
 );
 
+CREATE INDEX mempool_transactions_confirmed_height_idx ON mempool_transactions (confirmed_height);
+
+CREATE TABLE backfill_progress (
+                                   start_height INT PRIMARY KEY,
+                                   end_height INT NOT NULL,
+                                   next_height INT NOT NULL,
+                                   updated_at TIMESTAMP NOT NULL
+);
+
+
+
+-- Price at each block's timestamp per currency. A NULL price means the source had no data for it.
+CREATE TABLE block_prices (
+                              height INT NOT NULL,
+                              btc_price DOUBLE PRECISION,
+                              source VARCHAR NOT NULL,
+                              priced_at TIMESTAMP NOT NULL,
+                              currency VARCHAR NOT NULL DEFAULT 'usd',
+                              PRIMARY KEY (height, currency),
+                              FOREIGN KEY (height) REFERENCES block_info (height)
+);
+
+CREATE TABLE price_quotes (
+                              id SERIAL PRIMARY KEY,
+                              block_height INT NOT NULL,
+                              provider VARCHAR NOT NULL,
+                              price DOUBLE PRECISION NOT NULL,
+                              volume DOUBLE PRECISION,
+                              high DOUBLE PRECISION,
+                              low DOUBLE PRECISION,
+                              market_cap DOUBLE PRECISION,
+                              accepted BOOLEAN NOT NULL,
+                              fetched_at TIMESTAMP NOT NULL,
+                              currency VARCHAR NOT NULL DEFAULT 'usd'
+);
 
+CREATE INDEX price_quotes_block_height_idx ON price_quotes (block_height);
 
 CREATE TABLE IF NOT EXISTS offchain_data (
                                              id SERIAL PRIMARY KEY,
@@ -46,7 +139,8 @@ CREATE TABLE IF NOT EXISTS offchain_data (
                                              volume DOUBLE PRECISION,
                                              high DOUBLE PRECISION,
                                              low DOUBLE PRECISION,
-                                             timestamp TIMESTAMP NOT NULL
+                                             timestamp TIMESTAMP NOT NULL,
+                                             currency VARCHAR NOT NULL DEFAULT 'usd'
 );
 
 